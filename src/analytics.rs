@@ -0,0 +1,228 @@
+use crate::failure::Role;
+use crate::replay::{Frame, Position};
+use crate::stats::Stats;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Metrics of a match derived from its replay.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MatchAnalytics {
+    /// Number of rounds.
+    pub length: u32,
+    /// `(round, rollman score, ghost score)` at the start and at every change of the score.
+    pub score_timeline: Vec<(u32, i16, i16)>,
+    /// How many times the side with the higher score changed, not counting ties in between.
+    pub lead_changes: u32,
+    /// The round since which the score stayed final.
+    pub final_score_at: u32,
+    pub rollman_actions: BTreeMap<String, u32>,
+    pub ghost_actions: BTreeMap<String, u32>,
+    /// Total Manhattan distance moved by the rollman.
+    pub rollman_distance: u32,
+    /// Total Manhattan distance moved by all ghosts.
+    pub ghost_distance: u32,
+}
+
+impl MatchAnalytics {
+    /// Returns `None` if no frame has a score.
+    pub fn from_frames(frames: impl IntoIterator<Item = Frame>) -> Option<Self> {
+        let mut length = 0;
+        let mut score_timeline = Vec::<(u32, i16, i16)>::new();
+        let mut rollman_actions = BTreeMap::new();
+        let mut ghost_actions = BTreeMap::new();
+        let mut rollman_distance = 0;
+        let mut ghost_distance = 0;
+        let mut last_rollman: Option<Position> = None;
+        let mut last_ghosts = Vec::new();
+
+        for (index, frame) in frames.into_iter().enumerate() {
+            let round = frame.round.unwrap_or(index as u32);
+            length = length.max(round + 1);
+
+            if let Some((rollman, ghost)) = frame.score {
+                if score_timeline
+                    .last()
+                    .is_none_or(|&(_, r, g)| (r, g) != (rollman, ghost))
+                {
+                    score_timeline.push((round, rollman, ghost));
+                }
+            }
+
+            if let Some(action) = frame.actions.rollman {
+                *rollman_actions.entry(action.0).or_insert(0) += 1;
+            }
+            for action in frame.actions.ghost {
+                *ghost_actions.entry(action.0).or_insert(0) += 1;
+            }
+
+            if let Some(rollman) = frame.rollman {
+                if let Some(last) = last_rollman {
+                    rollman_distance += last.distance(rollman);
+                }
+                last_rollman = Some(rollman);
+            }
+            if !frame.ghosts.is_empty() {
+                if last_ghosts.len() == frame.ghosts.len() {
+                    ghost_distance += last_ghosts
+                        .iter()
+                        .zip(&frame.ghosts)
+                        .map(|(a, b): (&Position, &Position)| a.distance(*b))
+                        .sum::<u32>();
+                }
+                last_ghosts = frame.ghosts;
+            }
+        }
+
+        let &(final_score_at, _, _) = score_timeline.last()?;
+
+        let mut lead_changes = 0;
+        let mut leader = None;
+        for &(_, rollman, ghost) in &score_timeline {
+            let current = rollman.cmp(&ghost);
+            if current.is_eq() {
+                continue;
+            }
+            if leader.is_some_and(|leader| leader != current) {
+                lead_changes += 1;
+            }
+            leader = Some(current);
+        }
+
+        Some(Self {
+            length,
+            score_timeline,
+            lead_changes,
+            final_score_at,
+            rollman_actions,
+            ghost_actions,
+            rollman_distance,
+            ghost_distance,
+        })
+    }
+
+    /// `(rollman, ghost)`
+    pub fn final_score(&self) -> (i16, i16) {
+        let &(_, rollman, ghost) = self.score_timeline.last().unwrap();
+        (rollman, ghost)
+    }
+}
+
+/// Analytics of all matches an agent played in one role.
+pub struct RoleAnalytics {
+    pub count: usize,
+    pub mean_length: f32,
+    pub mean_lead_changes: f32,
+    pub mean_final_score_at: f32,
+    /// Distance moved by the agent per round.
+    pub speed: f32,
+    pub actions: BTreeMap<String, u32>,
+}
+
+impl RoleAnalytics {
+    fn new<'a>(
+        analytics: impl IntoIterator<Item = &'a MatchAnalytics>,
+        role: Role,
+    ) -> Option<Self> {
+        let mut count = 0;
+        let (mut length, mut lead_changes, mut final_score_at, mut distance) = (0, 0, 0, 0);
+        let mut actions = BTreeMap::new();
+        for a in analytics {
+            count += 1;
+            length += a.length as u64;
+            lead_changes += a.lead_changes as u64;
+            final_score_at += a.final_score_at as u64;
+            let (role_distance, role_actions) = match role {
+                Role::Rollman => (a.rollman_distance, &a.rollman_actions),
+                Role::Ghost => (a.ghost_distance, &a.ghost_actions),
+            };
+            distance += role_distance as u64;
+            for (action, n) in role_actions {
+                *actions.entry(action.clone()).or_insert(0) += n;
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        Some(Self {
+            count,
+            mean_length: length as f32 / count as f32,
+            mean_lead_changes: lead_changes as f32 / count as f32,
+            mean_final_score_at: final_score_at as f32 / count as f32,
+            speed: if length == 0 {
+                0.0
+            } else {
+                distance as f32 / length as f32
+            },
+            actions,
+        })
+    }
+
+    /// The most frequent actions with their share, e.g. "L 40%, U 30%, R 20%".
+    pub fn top_actions(&self, n: usize) -> String {
+        let total = self.actions.values().sum::<u32>().max(1);
+        let mut actions = self.actions.iter().collect::<Vec<_>>();
+        actions.sort_by_key(|(_, count)| Reverse(**count));
+        actions
+            .into_iter()
+            .take(n)
+            .map(|(action, count)| format!("{action} {}%", count * 100 / total))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Stats {
+    pub fn rollman_analytics(&self, token: &str) -> Option<RoleAnalytics> {
+        let matches = self.matches_with_rollman.get(token)?;
+        RoleAnalytics::new(
+            matches.iter().filter_map(|(id, _)| self.analytics.get(id)),
+            Role::Rollman,
+        )
+    }
+
+    pub fn ghost_analytics(&self, token: &str) -> Option<RoleAnalytics> {
+        let matches = self.matches_with_ghost.get(token)?;
+        RoleAnalytics::new(
+            matches.iter().filter_map(|(id, _)| self.analytics.get(id)),
+            Role::Ghost,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::FrameReader;
+
+    #[test]
+    fn replay_analytics() {
+        let replay = include_bytes!("../tests/fixtures/replay.jsonl");
+        let frames = FrameReader::new(&replay[..]).map(Result::unwrap);
+        let analytics = MatchAnalytics::from_frames(frames).unwrap();
+
+        assert_eq!(analytics.length, 4);
+        assert_eq!(
+            analytics.score_timeline,
+            [(0, 0, 0), (1, 1, 0), (2, 4, 1), (3, 7, 2)]
+        );
+        assert_eq!(analytics.final_score(), (7, 2));
+        assert_eq!(analytics.final_score_at, 3);
+        assert_eq!(analytics.lead_changes, 0);
+        assert_eq!(analytics.rollman_actions["R"], 2);
+        assert_eq!(analytics.ghost_actions.values().sum::<u32>(), 4);
+        assert_eq!(analytics.rollman_distance, 2);
+        assert_eq!(analytics.ghost_distance, 4);
+    }
+
+    #[test]
+    fn lead_changes() {
+        let frames = [(1, 0), (1, 1), (1, 2), (3, 2), (3, 2)].map(|(rollman, ghost)| {
+            serde_json::from_value(serde_json::json!({ "score": [rollman, ghost] })).unwrap()
+        });
+        let analytics = MatchAnalytics::from_frames(frames).unwrap();
+        assert_eq!(analytics.lead_changes, 2);
+        assert_eq!(analytics.final_score_at, 3);
+        assert_eq!(analytics.length, 5);
+    }
+}
@@ -0,0 +1,296 @@
+//! All requests to Saiblo, with rate limiting, timeouts and retries of transient failures.
+
+use crate::config::{ApiConfig, Config, SaibloConfig, TOKEN_HEADER};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::io::{ErrorKind, Read};
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use ureq::http::{Response, StatusCode};
+use ureq::{Agent, Body};
+
+/// Requests are grouped by what they do, each with its own timeout and retry rule.
+#[derive(Clone, Copy)]
+pub enum Endpoint {
+    /// Listing and counting matches.
+    List,
+    /// Downloading replays.
+    Download,
+    /// Creating rooms and starting matches. These are not idempotent, so they are only retried if
+    /// Saiblo surely has not handled them.
+    Create,
+}
+
+pub struct Api<'a> {
+    pub saiblo: &'a SaibloConfig,
+    config: &'a ApiConfig,
+    agent: Agent,
+    /// When the next request may be sent.
+    next_request: Mutex<Instant>,
+}
+
+/// The failure of a single attempt.
+struct Attempt {
+    error: Report,
+    retryable: bool,
+    /// From the Retry-After header.
+    retry_after: Option<Duration>,
+}
+
+impl Attempt {
+    fn fatal(error: impl Into<Report>) -> Self {
+        Self {
+            error: error.into(),
+            retryable: false,
+            retry_after: None,
+        }
+    }
+}
+
+impl<'a> Api<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        let agent =
+            Agent::new_with_config(Agent::config_builder().http_status_as_error(false).build());
+        Self {
+            saiblo: &config.saiblo,
+            config: &config.api,
+            agent,
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn get_json<T: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let body = self.send(endpoint, path, || {
+            let mut req = self.agent.get(self.url(path));
+            for (key, value) in query {
+                req = req.query(*key, value);
+            }
+            req.config()
+                .timeout_global(Some(self.config.timeout(endpoint)))
+                .build()
+                .header(TOKEN_HEADER, self.saiblo.token()?)
+                .call()
+                .map_err(Into::into)
+        })?;
+        serde_json::from_slice(&body).wrap_err_with(|| format!("invalid response of {path}"))
+    }
+
+    pub fn download(&self, path: &str) -> Result<Vec<u8>> {
+        self.send(Endpoint::Download, path, || {
+            self.agent
+                .get(self.url(path))
+                .config()
+                .timeout_global(Some(self.config.timeout(Endpoint::Download)))
+                .build()
+                .header(TOKEN_HEADER, self.saiblo.token()?)
+                .call()
+                .map_err(Into::into)
+        })
+    }
+
+    /// Posts `json`, or an empty body if `None`, and returns the response body.
+    pub fn post(&self, path: &str, json: Option<&str>) -> Result<Vec<u8>> {
+        self.send(Endpoint::Create, path, || {
+            let req = self
+                .agent
+                .post(self.url(path))
+                .config()
+                .timeout_global(Some(self.config.timeout(Endpoint::Create)))
+                .build()
+                .header(TOKEN_HEADER, self.saiblo.token()?);
+            // `send_json` uses Transfer-Encoding: chunked, which is somehow not recognized by
+            // Saiblo
+            match json {
+                Some(json) => req.header("Content-Type", "application/json").send(json),
+                None => req.send_empty(),
+            }
+            .map_err(Into::into)
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.saiblo.base_url)
+    }
+
+    /// Sends the request made by `request` until it succeeds, a non-retryable error occurs, or
+    /// `max_retries` retries have failed, and returns the response body.
+    fn send(
+        &self,
+        endpoint: Endpoint,
+        path: &str,
+        request: impl Fn() -> Result<Response<Body>>,
+    ) -> Result<Vec<u8>> {
+        let mut retries = 0;
+        loop {
+            self.wait_for_rate_limit();
+            let attempt = request()
+                .map_err(|e| match e.downcast::<ureq::Error>() {
+                    Ok(e) => classify(e, endpoint),
+                    Err(e) => Attempt::fatal(e),
+                })
+                .and_then(|res| read_body(res, endpoint));
+            let failure = match attempt {
+                Ok(body) => return Ok(body),
+                Err(failure) => failure,
+            };
+            if !failure.retryable || retries >= self.config.max_retries {
+                return Err(failure.error.wrap_err(format!("request to {path} failed")));
+            }
+            let delay = self.config.retry_delay(retries, failure.retry_after);
+            eprintln!(
+                "Request to {path} failed, retrying in {:.1}s: {}",
+                delay.as_secs_f32(),
+                failure.error,
+            );
+            sleep(delay);
+            retries += 1;
+        }
+    }
+
+    fn wait_for_rate_limit(&self) {
+        if self.config.requests_per_second <= 0.0 {
+            return;
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.config.requests_per_second);
+        let now = Instant::now();
+        let at = {
+            let mut next = self.next_request.lock().unwrap();
+            let at = (*next).max(now);
+            *next = at + interval;
+            at
+        };
+        sleep(at - now);
+    }
+}
+
+fn read_body(res: Response<Body>, endpoint: Endpoint) -> Result<Vec<u8>, Attempt> {
+    let status = res.status();
+    let retry_after = res
+        .headers()
+        .get("Retry-After")
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .map(Duration::from_secs);
+    let mut body = Vec::new();
+    let read = res.into_body().into_reader().read_to_end(&mut body);
+
+    if !status.is_success() {
+        let text = String::from_utf8_lossy(&body);
+        return Err(Attempt {
+            error: eyre!("{status}: {}", text.chars().take(200).collect::<String>()),
+            retryable: retryable_status(status, endpoint),
+            retry_after,
+        });
+    }
+    match read {
+        Ok(_) => Ok(body),
+        // The request was handled if the response has started.
+        Err(e) => Err(Attempt {
+            error: e.into(),
+            retryable: !matches!(endpoint, Endpoint::Create),
+            retry_after: None,
+        }),
+    }
+}
+
+fn retryable_status(status: StatusCode, endpoint: Endpoint) -> bool {
+    match endpoint {
+        Endpoint::List | Endpoint::Download => {
+            status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+        }
+        Endpoint::Create => matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ),
+    }
+}
+
+fn classify(error: ureq::Error, endpoint: Endpoint) -> Attempt {
+    let retryable = match &error {
+        ureq::Error::ConnectionFailed | ureq::Error::HostNotFound => true,
+        ureq::Error::Io(e) if e.kind() == ErrorKind::ConnectionRefused => true,
+        ureq::Error::Timeout(_) | ureq::Error::Io(_) | ureq::Error::BodyStalled => {
+            !matches!(endpoint, Endpoint::Create)
+        }
+        _ => false,
+    };
+    Attempt {
+        error: error.into(),
+        retryable,
+        retry_after: None,
+    }
+}
+
+impl ApiConfig {
+    pub fn timeout(&self, endpoint: Endpoint) -> Duration {
+        Duration::from_secs(match endpoint {
+            Endpoint::List => self.list_timeout_secs,
+            Endpoint::Download => self.download_timeout_secs,
+            Endpoint::Create => self.create_timeout_secs,
+        })
+    }
+
+    /// The delay before retry number `retries + 1`: exponential with jitter, at most
+    /// `max_backoff_ms`.
+    pub fn backoff(&self, retries: u32) -> Duration {
+        let max = self
+            .backoff_ms
+            .saturating_mul(1 << retries.min(16))
+            .min(self.max_backoff_ms);
+        Duration::from_millis(rand::rng().random_range(max / 2..=max))
+    }
+
+    /// The delay before retry number `retries + 1`: the server's Retry-After if it sent one, at
+    /// most `max_backoff_ms` so a bad header can't stall fetching, or else `backoff`.
+    pub fn retry_delay(&self, retries: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(delay) => delay.min(Duration::from_millis(self.max_backoff_ms)),
+            None => self.backoff(retries),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_rules() {
+        let config = ApiConfig::default();
+        assert!(config.backoff(0) <= Duration::from_millis(config.backoff_ms));
+        assert!(config.backoff(30) >= Duration::from_millis(config.max_backoff_ms / 2));
+        assert!(config.backoff(30) <= Duration::from_millis(config.max_backoff_ms));
+        assert_eq!(
+            config.retry_delay(0, Some(Duration::from_secs(1))),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            config.retry_delay(0, Some(Duration::from_secs(u32::MAX.into()))),
+            Duration::from_millis(config.max_backoff_ms)
+        );
+
+        assert!(classify(ureq::Error::ConnectionFailed, Endpoint::Create).retryable);
+        assert!(classify(ureq::Error::Timeout(ureq::Timeout::Global), Endpoint::List).retryable);
+        assert!(
+            !classify(
+                ureq::Error::Timeout(ureq::Timeout::Global),
+                Endpoint::Create
+            )
+            .retryable
+        );
+        assert!(!classify(ureq::Error::BadUri(String::new()), Endpoint::List).retryable);
+
+        assert!(retryable_status(
+            StatusCode::BAD_GATEWAY,
+            Endpoint::Download
+        ));
+        assert!(!retryable_status(StatusCode::BAD_GATEWAY, Endpoint::Create));
+        assert!(!retryable_status(StatusCode::NOT_FOUND, Endpoint::List));
+    }
+}
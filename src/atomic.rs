@@ -0,0 +1,80 @@
+use color_eyre::eyre::Result;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A file that is written to `<path>.tmp` and only renamed over `path` on `commit`, so a crash
+/// midway leaves the previous content intact.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    buf: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let tmp = with_suffix(&path, ".tmp");
+        let buf = BufWriter::new(File::create(&tmp)?);
+        Ok(Self {
+            path,
+            tmp,
+            buf: Some(buf),
+        })
+    }
+
+    /// Keeps the current content of the target as `<path>.1`, shifting older backups up to
+    /// `<path>.<count>`, before replacing it.
+    pub fn commit_with_backups(self, count: usize) -> Result<()> {
+        if count > 0 && self.path.exists() {
+            for i in (1..count).rev() {
+                let from = with_suffix(&self.path, &format!(".{i}"));
+                if from.exists() {
+                    fs::rename(&from, with_suffix(&self.path, &format!(".{}", i + 1)))?;
+                }
+            }
+            let first = with_suffix(&self.path, ".1");
+            // A hard link keeps `path` in place until the rename below.
+            if fs::hard_link(&self.path, &first).is_err() {
+                fs::copy(&self.path, &first)?;
+            }
+        }
+        self.commit()
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.buf.take().unwrap().into_inner()?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.tmp, &self.path)?;
+        if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.as_mut().unwrap().write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buf.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.buf.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    name.into()
+}
@@ -0,0 +1,165 @@
+use crate::dataset::Format;
+use crate::failure::{failure_breakdown, Role};
+use crate::stats::Stats;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{eyre, Result};
+use ordered_float::OrderedFloat;
+use std::cmp::Reverse;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(about = "Elo rating for RollMan on Saiblo")]
+pub struct Cli {
+    /// Defaults to `rollman.toml` if it exists.
+    #[arg(long, global = true, env = "ROLLMAN_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Runs `run` if omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fetch new matches, save them with the reports and schedule new matches.
+    Run,
+    /// Keep running `run`, fetching and scheduling on the intervals in the `daemon` settings.
+    Daemon,
+    /// Fetch new matches and save them with the reports.
+    Fetch {
+        #[arg(long, default_value_t = 100)]
+        max_pages: usize,
+    },
+    /// Print the ranking computed from the stored matches.
+    Rate {
+        #[arg(long, value_enum, default_value_t)]
+        system: RatingSystem,
+        /// How many agents to print per role.
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Create matches on Saiblo.
+    Schedule {
+        /// Print the pairs instead of creating the matches.
+        #[arg(long)]
+        dry_run: bool,
+        /// How many matches to create. By default, enough to have `schedule.max_matches` judging
+        /// or waiting ones.
+        #[arg(long)]
+        count: Option<usize>,
+    },
+    /// Regenerate the reports from the stored data, without network access.
+    #[command(alias = "offline")]
+    Report {
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Look up stored data.
+    Query {
+        #[command(subcommand)]
+        query: Query,
+    },
+    /// Export the matches and agents into a directory.
+    Export {
+        /// csv, jsonl or parquet
+        format: Format,
+        dir: PathBuf,
+    },
+    /// Add matches and agents exported by `export` to the storage.
+    Import {
+        /// csv, jsonl or parquet
+        format: Format,
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Query {
+    /// Print the ratings and statistics of an agent.
+    Agent { token: String },
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum RatingSystem {
+    /// The Elo rating used by the ranking.
+    #[default]
+    Elo,
+    /// The mean score in all matches.
+    Score,
+}
+
+pub fn rate(stats: &Stats, system: RatingSystem, top: usize) {
+    for role in [Role::Rollman, Role::Ghost] {
+        let mut agents = stats
+            .agents
+            .iter()
+            .filter(|(_, a)| match role {
+                Role::Rollman => a.can_rollman(),
+                Role::Ghost => a.can_ghost(),
+            })
+            .filter_map(|(token, a)| {
+                let rating = match (system, role) {
+                    (RatingSystem::Elo, Role::Rollman) => a.rollman_elo,
+                    (RatingSystem::Elo, Role::Ghost) => a.ghost_elo,
+                    (RatingSystem::Score, Role::Rollman) => stats.rollman_score_stats(token)?.mean,
+                    (RatingSystem::Score, Role::Ghost) => stats.ghost_score_stats(token)?.mean,
+                };
+                Some((token, rating))
+            })
+            .collect::<Vec<_>>();
+        agents.sort_by_key(|(_, rating)| Reverse(OrderedFloat(*rating)));
+
+        println!("{role}:");
+        for (rank, (token, rating)) in agents.into_iter().take(top).enumerate() {
+            println!("{:>4} {rating:>8.1} {}", rank + 1, stats.describe(token));
+        }
+    }
+}
+
+pub fn query_agent(stats: &Stats, token: &str) -> Result<()> {
+    let agent = stats
+        .agents
+        .get(token)
+        .ok_or_else(|| eyre!("unknown agent: {token}"))?;
+    println!("{}", stats.describe(token));
+
+    for role in [Role::Rollman, Role::Ghost] {
+        let (elo, count, score_stats, analytics) = match role {
+            Role::Rollman => (
+                agent.rollman_elo,
+                agent.rollman_count,
+                stats.rollman_score_stats(token),
+                stats.rollman_analytics(token),
+            ),
+            Role::Ghost => (
+                agent.ghost_elo,
+                agent.ghost_count,
+                stats.ghost_score_stats(token),
+                stats.ghost_analytics(token),
+            ),
+        };
+        println!("{role}: Elo {elo:.0}, {count} matches");
+        if let Some(s) = score_stats {
+            println!(
+                "  score {:.1} ± {:.1} (min {}, median {:.1}, max {}), trend {:+.2}",
+                s.mean, s.std_dev, s.min, s.quantiles[1], s.max, s.trend,
+            );
+        }
+        if let Some(a) = analytics {
+            println!(
+                "  {:.1} rounds, {:.2} lead changes, speed {:.2}, actions {}",
+                a.mean_length,
+                a.mean_lead_changes,
+                a.speed,
+                a.top_actions(3),
+            );
+        }
+    }
+
+    println!(
+        "failures: {} ({})",
+        agent.failure.len(),
+        failure_breakdown(agent.failure.values()),
+    );
+    Ok(())
+}
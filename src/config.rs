@@ -0,0 +1,455 @@
+//! Settings read from a TOML file, see `config.example.toml` for all of them with their defaults.
+//!
+//! Any setting can be overridden by the environment variable `ROLLMAN_<SECTION>_<KEY>`, e.g.
+//! `ROLLMAN_RATING_K=6`. `SAIBLO_TOKEN`, `STORAGE` and `FAILURE_POLICY` are also accepted.
+
+use crate::failure::FailurePolicy;
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use color_eyre::eyre::{bail, OptionExt, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Read if it exists and no other path is given.
+pub const DEFAULT_CONFIG: &str = "rollman.toml";
+
+pub const TOKEN_HEADER: &str = "Authorization";
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub saiblo: SaibloConfig,
+    pub api: ApiConfig,
+    pub storage: StorageConfig,
+    pub replay_cache: ReplayCacheConfig,
+    pub rating: RatingConfig,
+    pub schedule: ScheduleConfig,
+    pub report: ReportConfig,
+    pub daemon: DaemonConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SaibloConfig {
+    pub base_url: String,
+    pub game_id: u32,
+    /// Matches per page when fetching.
+    pub page_size: usize,
+    /// How long a match may be judged, or go without a replay, before it is reported as stuck.
+    pub pending_timeout_minutes: i64,
+    /// Only required when talking to Saiblo.
+    pub token: Option<String>,
+}
+
+impl Default for SaibloConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.saiblo.net/api".to_string(),
+            game_id: 42,
+            page_size: 20,
+            pending_timeout_minutes: 120,
+            token: None,
+        }
+    }
+}
+
+impl SaibloConfig {
+    pub fn token(&self) -> Result<&str> {
+        self.token.as_deref().ok_or_eyre("SAIBLO_TOKEN not set")
+    }
+
+    pub fn pending_timeout(&self) -> TimeDelta {
+        TimeDelta::minutes(self.pending_timeout_minutes)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// 0 for no limit.
+    pub requests_per_second: f64,
+    /// How many times a request is retried after a transient failure.
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled for each further one.
+    pub backoff_ms: u64,
+    /// Also caps Retry-After.
+    pub max_backoff_ms: u64,
+    pub list_timeout_secs: u64,
+    pub download_timeout_secs: u64,
+    pub create_timeout_secs: u64,
+    /// How many replays are downloaded at once.
+    pub download_workers: usize,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 5.0,
+            max_retries: 4,
+            backoff_ms: 1000,
+            max_backoff_ms: 60000,
+            list_timeout_secs: 30,
+            download_timeout_secs: 120,
+            create_timeout_secs: 30,
+            download_workers: 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `*.json` or `*.sqlite`, see `storage::open`.
+    pub path: PathBuf,
+    /// How many previous versions of a JSON storage are kept as `<storage>.1`, `<storage>.2`, ...
+    pub backup_count: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            path: "storage.json".into(),
+            backup_count: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayCacheConfig {
+    pub dir: PathBuf,
+    pub compress: bool,
+    pub max_bytes: u64,
+}
+
+impl Default for ReplayCacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: "replays".into(),
+            compress: true,
+            max_bytes: 8 << 30,
+        }
+    }
+}
+
+/// The default `elo_base`, the rating of new agents.
+const DEFAULT_ELO_BASE: f32 = 1500.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatingConfig {
+    pub elo_base: f32,
+    pub elo_step: f32,
+    /// The K-factor, scaled by the opponent's rating and the number of compared matches.
+    pub k: f32,
+    pub failure_policy: FailurePolicy,
+}
+
+impl Default for RatingConfig {
+    fn default() -> Self {
+        Self {
+            elo_base: DEFAULT_ELO_BASE,
+            elo_step: 300.0,
+            k: 5.0,
+            failure_policy: FailurePolicy::Ignore,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// How many matches may be judging or waiting at once.
+    pub max_matches: usize,
+    /// Agents rated at or below this are not scheduled. 200 below the default `elo_base` by
+    /// default.
+    pub min_rating: f32,
+    /// A pair is scheduled less the higher `(diff - sum / rating_sum_divisor) / (elo_step *
+    /// rating_scale) + rank_penalty * (rank of the rollman + rank of the ghost)` is, where ranks
+    /// are among the agents of the same user.
+    pub rating_sum_divisor: f32,
+    pub rating_scale: f32,
+    pub rank_penalty: f32,
+    /// No matches are created during these windows, but matches are still fetched.
+    pub blackouts: Vec<BlackoutWindow>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            max_matches: 210,
+            min_rating: DEFAULT_ELO_BASE - 200.0,
+            rating_sum_divisor: 1.5,
+            rating_scale: 1.5,
+            rank_penalty: 0.1,
+            blackouts: vec![BlackoutWindow {
+                start: NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+                weekdays: Vec::new(),
+                time_zone: None,
+            }],
+        }
+    }
+}
+
+impl ScheduleConfig {
+    pub fn blackout_at(&self, time: DateTime<Utc>) -> Option<&BlackoutWindow> {
+        self.blackouts.iter().find(|window| window.contains(time))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlackoutWindow {
+    pub start: NaiveTime,
+    /// Before `start` if the window spans midnight.
+    pub end: NaiveTime,
+    /// The days on which the window starts, every day if empty.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    /// The local time zone if not set.
+    pub time_zone: Option<Tz>,
+}
+
+impl BlackoutWindow {
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        let time = match self.time_zone {
+            Some(tz) => time.with_timezone(&tz).naive_local(),
+            None => time.with_timezone(&Local).naive_local(),
+        };
+        let (day, time) = (time.weekday(), time.time());
+        let starts_on = |day| self.weekdays.is_empty() || self.weekdays.contains(&day);
+        if self.start <= self.end {
+            starts_on(day) && self.start <= time && time < self.end
+        } else {
+            (starts_on(day) && self.start <= time) || (starts_on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+impl fmt::Display for BlackoutWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )?;
+        if !self.weekdays.is_empty() {
+            let weekdays = self.weekdays.iter().map(Weekday::to_string);
+            write!(f, " on {}", weekdays.collect::<Vec<_>>().join(", "))?;
+        }
+        if let Some(tz) = self.time_zone {
+            write!(f, " ({tz})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    /// Agents are highlighted as new for this long after their first match.
+    pub recent_hours: i64,
+    /// Used instead of `recent_hours`, in match ids, for matches without a creation time.
+    pub recent_threshold: u32,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            recent_hours: 72,
+            recent_threshold: 10000,
+        }
+    }
+}
+
+impl ReportConfig {
+    pub fn recent_duration(&self) -> TimeDelta {
+        TimeDelta::hours(self.recent_hours)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// New matches are fetched and the reports are saved this often.
+    pub fetch_interval_minutes: u64,
+    pub schedule_interval_minutes: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            fetch_interval_minutes: 10,
+            schedule_interval_minutes: 10,
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn fetch_interval(&self) -> Duration {
+        Duration::from_secs(self.fetch_interval_minutes * 60)
+    }
+
+    pub fn schedule_interval(&self) -> Duration {
+        Duration::from_secs(self.schedule_interval_minutes * 60)
+    }
+}
+
+impl Config {
+    /// Reads `path`, or `DEFAULT_CONFIG` if it exists, and applies the environment overrides.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let content = match path {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .wrap_err_with(|| format!("failed to read {}", path.display()))?,
+            ),
+            None => match fs::read_to_string(DEFAULT_CONFIG) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            },
+        };
+        let mut table = match content {
+            Some(content) => content.parse::<Table>()?,
+            None => Table::new(),
+        };
+        apply_env(&mut table, std::env::vars())?;
+        Ok(table.try_into()?)
+    }
+}
+
+/// Sets `table[section][key]` for every variable that overrides a setting.
+fn apply_env(table: &mut Table, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+    let defaults = Table::try_from(Config::default())?;
+
+    for (var, value) in vars {
+        let (section, key) = match var.as_str() {
+            "SAIBLO_TOKEN" => ("saiblo", "token".to_string()),
+            "STORAGE" => ("storage", "path".to_string()),
+            "FAILURE_POLICY" => ("rating", "failure_policy".to_string()),
+            _ => {
+                let Some(rest) = var.strip_prefix("ROLLMAN_") else {
+                    continue;
+                };
+                let Some((section, key)) = defaults.keys().find_map(|section| {
+                    let key = rest.strip_prefix(&format!("{}_", section.to_uppercase()))?;
+                    Some((section.as_str(), key.to_lowercase()))
+                }) else {
+                    continue;
+                };
+                (section, key)
+            }
+        };
+
+        // Strings are taken verbatim, anything else is parsed as a TOML value.
+        let default = defaults[section].get(&key);
+        let value = if default.is_none_or(Value::is_str) {
+            Value::String(value)
+        } else {
+            match format!("value = {value}").parse::<Table>() {
+                Ok(mut parsed) => parsed.remove("value").unwrap(),
+                Err(_) => bail!("invalid value of {var}: {value}"),
+            }
+        };
+
+        table
+            .entry(section)
+            .or_insert_with(|| Table::new().into())
+            .as_table_mut()
+            .ok_or_eyre(format!("{section} is not a table"))?
+            .insert(key, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_is_default() {
+        let example = include_str!("../config.example.toml")
+            .parse::<Table>()
+            .unwrap();
+        let defaults = Table::try_from(Config::default()).unwrap();
+        for (section, settings) in &defaults {
+            let keys = |table: &Value| {
+                table
+                    .as_table()
+                    .unwrap()
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(keys(&example[section]), keys(settings));
+        }
+        let example: Config = example.try_into().unwrap();
+        assert_eq!(Table::try_from(example).unwrap(), defaults);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut table = "[rating]\nk = 4.0\nelo_base = 1000.0"
+            .parse::<Table>()
+            .unwrap();
+        let vars = [
+            ("ROLLMAN_RATING_K", "6"),
+            ("ROLLMAN_REPLAY_CACHE_COMPRESS", "false"),
+            ("ROLLMAN_SAIBLO_BASE_URL", "http://localhost:8000"),
+            ("SAIBLO_TOKEN", "123"),
+            ("FAILURE_POLICY", "worst-score"),
+            ("HOME", "/root"),
+        ]
+        .map(|(var, value)| (var.to_string(), value.to_string()));
+        apply_env(&mut table, vars).unwrap();
+        let config: Config = table.try_into().unwrap();
+
+        assert_eq!(config.rating.k, 6.0);
+        assert_eq!(config.rating.elo_base, 1000.0);
+        assert_eq!(config.rating.failure_policy, FailurePolicy::WorstScore);
+        assert!(!config.replay_cache.compress);
+        assert_eq!(config.saiblo.base_url, "http://localhost:8000");
+        assert_eq!(config.saiblo.token().unwrap(), "123");
+        assert_eq!(config.schedule.max_matches, 210);
+    }
+
+    #[test]
+    fn invalid_env() {
+        let mut table = Table::new();
+        let vars = [(
+            "ROLLMAN_SCHEDULE_MAX_MATCHES".to_string(),
+            "many".to_string(),
+        )];
+        assert!(apply_env(&mut table, vars).is_err());
+    }
+
+    #[test]
+    fn blackout_windows() {
+        let config: ScheduleConfig = toml::from_str(
+            r#"
+            [[blackouts]]
+            start = "22:00"
+            end = "02:00"
+            weekdays = ["Fri"]
+            time_zone = "Asia/Shanghai"
+            "#,
+        )
+        .unwrap();
+        let at = |time: &str| config.blackout_at(time.parse().unwrap()).is_some();
+
+        // 2026-10-16 is a Friday.
+        assert!(!at("2026-10-16T13:59:00Z"));
+        assert!(at("2026-10-16T14:00:00Z"));
+        assert!(at("2026-10-16T17:59:00Z"));
+        assert!(!at("2026-10-16T18:00:00Z"));
+        assert!(!at("2026-10-17T14:30:00Z"));
+        assert!(!at("2026-10-15T17:00:00Z"));
+    }
+}
@@ -1,91 +1,41 @@
-use crate::constants::*;
+use crate::config::Config;
+use crate::saiblo::{Saiblo, JUDGING, WAITING};
 use crate::stats::Stats;
-use color_eyre::eyre::Result;
+use chrono::Utc;
+use color_eyre::eyre::{Result, WrapErr};
 use ordered_float::NotNan;
 use rand::prelude::*;
-use serde::{Deserialize, Serialize};
 use std::cmp::Reverse;
 use std::collections::HashMap;
-use ureq::Body;
 
-#[derive(Serialize)]
-struct GameInfo {
-    game_id: u32,
-    player_number: u32,
+pub fn create_match(saiblo: &dyn Saiblo, rollman: &str, ghost: &str) -> Result<()> {
+    let room = saiblo.create_room()?;
+    saiblo.join_room(room, rollman, 0)?;
+    saiblo.join_room(room, ghost, 1)?;
+    saiblo.begin_match(room)
 }
 
-const GAME_INFO: GameInfo = GameInfo {
-    game_id: GAME_ID,
-    player_number: 2,
-};
-
-#[derive(Deserialize)]
-struct Room {
-    id: u32,
-}
-
-#[derive(Serialize)]
-struct Join<'a> {
-    enter: bool,
-    entity: &'a str,
-    is_remote: bool,
-    is_user: bool,
-    order: u8,
-}
-
-// `send_json` uses Transfer-Encoding: chunked, which is somehow not recognized by Saiblo
-fn post(url: &str, json: impl Serialize) -> Result<Body> {
-    let data = serde_json::to_string(&json)?;
-    let res = ureq::post(url)
-        .header(TOKEN_HEADER, &*TOKEN)
-        .header("Content-Type", "application/json")
-        .send(data)?
-        .into_body();
-    Ok(res)
-}
-
-pub fn create_match(rollman: &str, ghost: &str) -> Result<()> {
-    let room: Room = post(&format!("{BASE_URL}/rooms/"), GAME_INFO)?.read_json()?;
-    let url = format!("{BASE_URL}/rooms/{}", room.id);
-
-    let rollman_join = Join {
-        enter: true,
-        entity: rollman,
-        is_remote: false,
-        is_user: false,
-        order: 0,
-    };
-    post(&format!("{url}/join/"), rollman_join)?;
-
-    let ghost_join = Join {
-        enter: true,
-        entity: ghost,
-        is_remote: false,
-        is_user: false,
-        order: 1,
-    };
-    post(&format!("{url}/join/"), ghost_join)?;
-
-    ureq::post(format!("{url}/begin_match/"))
-        .header(TOKEN_HEADER, &*TOKEN)
-        .send_empty()?;
-
-    Ok(())
-}
-
-#[derive(Deserialize)]
-struct Count {
-    count: usize,
-}
-
-pub fn create_matches(stats: &Stats) {
-    const THRESHOLD: f32 = ELO_BASE - ELO_STEP / 1.5;
+/// Creates `count` matches, or as many as needed to have `max_matches` judging or waiting ones.
+/// With `dry_run`, the pairs are only printed.
+pub fn create_matches(
+    stats: &Stats,
+    saiblo: &dyn Saiblo,
+    config: &Config,
+    count: Option<usize>,
+    dry_run: bool,
+) -> Result<()> {
+    let schedule = &config.schedule;
+
+    if let Some(window) = schedule.blackout_at(Utc::now()).filter(|_| !dry_run) {
+        println!("Not creating matches during the blackout window {window}");
+        return Ok(());
+    }
 
     let mut pairs = Vec::new();
 
     let mut rollmen_by_user = HashMap::new();
     for (rollman, elo, user) in stats.agents.iter().filter_map(|(id, agent)| {
-        (agent.can_rollman() && agent.rollman_elo > THRESHOLD).then_some((
+        (agent.can_rollman() && agent.rollman_elo > schedule.min_rating).then_some((
             id,
             agent.rollman_elo,
             agent.user.clone(),
@@ -106,7 +56,7 @@ pub fn create_matches(stats: &Stats) {
 
     let mut ghosts_by_user = HashMap::new();
     for (ghost, elo, user) in stats.agents.iter().filter_map(|(id, agent)| {
-        (agent.can_ghost() && agent.ghost_elo > THRESHOLD).then_some((
+        (agent.can_ghost() && agent.ghost_elo > schedule.min_rating).then_some((
             id,
             agent.ghost_elo,
             agent.user.clone(),
@@ -137,47 +87,81 @@ pub fn create_matches(stats: &Stats) {
                 .unwrap_or_default();
             let diff = (rollman_elo - ghost_elo).abs();
             let sum = rollman_elo + ghost_elo;
-            let weight = ((diff - sum / 1.5) / (ELO_STEP * 1.5)
-                + (rollman_rank + ghost_rank) as f32 / 10.0)
+            let weight = ((diff - sum / schedule.rating_sum_divisor)
+                / (config.rating.elo_step * schedule.rating_scale)
+                + (rollman_rank + ghost_rank) as f32 * schedule.rank_penalty)
                 .exp();
-            pairs.push((
-                rollman,
-                ghost,
-                NotNan::new(count as f32 * weight).expect("nan"),
-            ));
+            pairs.push((rollman, ghost, NotNan::new(count as f32 * weight)?));
         }
     }
 
     pairs.shuffle(&mut rand::rng());
     pairs.sort_unstable_by_key(|(_, _, count)| *count);
 
-    let judging: Count = ureq::get(format!("{BASE_URL}/matches/"))
-        .query("limit", "1")
-        .query("state", "评测中")
-        .query("game", GAME_ID.to_string())
-        .header(TOKEN_HEADER, &*TOKEN)
-        .call()
-        .expect("failed to get judging matches")
-        .into_body()
-        .read_json()
-        .expect("invalid matches JSON");
-    let waiting: Count = ureq::get(format!("{BASE_URL}/matches/"))
-        .query("limit", "1")
-        .query("state", "准备中")
-        .query("game", GAME_ID.to_string())
-        .header(TOKEN_HEADER, &*TOKEN)
-        .call()
-        .expect("failed to get waiting matches")
-        .into_body()
-        .read_json()
-        .expect("invalid matches JSON");
-    let create_count = MAX_MATCHES.saturating_sub(judging.count + waiting.count);
+    let create_count = match count {
+        Some(count) => count,
+        None => {
+            let judging = saiblo
+                .count_matches_by_state(JUDGING)
+                .wrap_err("failed to get judging matches")?;
+            let waiting = saiblo
+                .count_matches_by_state(WAITING)
+                .wrap_err("failed to get waiting matches")?;
+            schedule.max_matches.saturating_sub(judging + waiting)
+        }
+    };
 
     println!("Creating {create_count} matches...");
 
     for (rollman, ghost, _) in pairs.into_iter().take(create_count) {
-        if let Err(e) = create_match(rollman, ghost) {
+        if dry_run {
+            println!("{} vs {}", stats.describe(rollman), stats.describe(ghost));
+            continue;
+        }
+        if let Err(e) = create_match(saiblo, rollman, ghost) {
             eprintln!("Failed to create match:\n{e:?}");
         }
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saiblo::fake::FakeSaiblo;
+    use crate::stats::Match;
+
+    #[test]
+    fn create_with_fake() {
+        let mut stats = Stats::default();
+        for token in ["a", "b"] {
+            stats.add_agent(token, format!("{token}-user"), "bot".to_string(), 1);
+        }
+        let m = |rollman: &str, ghost: &str| Match {
+            rollman: rollman.to_string(),
+            ghost: ghost.to_string(),
+            rollman_score: 5,
+            ghost_score: 5,
+            logic_version: 0,
+            created_at: None,
+            finished_at: None,
+            room_id: None,
+            creator: None,
+        };
+        stats.add_match(1, m("a", "b"));
+        stats.add_match(2, m("b", "a"));
+
+        let mut config = Config::default();
+        config.schedule.blackouts.clear();
+        let saiblo = FakeSaiblo::default();
+        create_matches(&stats, &saiblo, &config, Some(1), true).unwrap();
+        assert!(saiblo.rooms.lock().unwrap().is_empty());
+
+        create_matches(&stats, &saiblo, &config, None, false).unwrap();
+        let rooms = saiblo.rooms.lock().unwrap();
+        assert!(!rooms.is_empty());
+        assert!(rooms.iter().all(|players| players.len() == 2));
+        assert_eq!(saiblo.begun.lock().unwrap().len(), rooms.len());
+    }
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// The id ranges of the Saiblo match list that have been listed completely, so every match of the
+/// game in them has been seen. Whatever is between the ranges still has to be fetched.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FetchCursor {
+    /// Inclusive, disjoint and not adjacent, oldest first.
+    ranges: Vec<(u32, u32)>,
+}
+
+impl FetchCursor {
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    /// Marks `start..=end` as listed, merging it with the ranges it overlaps or touches.
+    pub fn insert(&mut self, start: u32, end: u32) {
+        let (mut start, mut end) = (start.min(end), start.max(end));
+        self.ranges.retain(|&(s, e)| {
+            let touches = s <= end.saturating_add(1) && start <= e.saturating_add(1);
+            if touches {
+                start = start.min(s);
+                end = end.max(e);
+            }
+            !touches
+        });
+        let index = self.ranges.partition_point(|&(s, _)| s < start);
+        self.ranges.insert(index, (start, end));
+    }
+
+    pub fn range_containing(&self, id: u32) -> Option<(u32, u32)> {
+        let index = self.ranges.partition_point(|&(_, e)| e < id);
+        self.ranges.get(index).copied().filter(|&(s, _)| s <= id)
+    }
+
+    /// The number of unlisted ranges below the newest listed id.
+    pub fn gaps(&self) -> usize {
+        match self.ranges.first() {
+            Some(&(0, _)) => self.ranges.len() - 1,
+            Some(_) => self.ranges.len(),
+            None => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_ranges() {
+        let mut cursor = FetchCursor::default();
+        assert_eq!(cursor.gaps(), 1);
+        cursor.insert(20, 30);
+        cursor.insert(50, 40);
+        cursor.insert(5, 10);
+        assert_eq!(cursor.ranges(), [(5, 10), (20, 30), (40, 50)]);
+        assert_eq!(cursor.gaps(), 3);
+        assert_eq!(cursor.range_containing(25), Some((20, 30)));
+        assert_eq!(cursor.range_containing(35), None);
+
+        cursor.insert(31, 39);
+        assert_eq!(cursor.ranges(), [(5, 10), (20, 50)]);
+        cursor.insert(0, 25);
+        assert_eq!(cursor.ranges(), [(0, 50)]);
+        assert_eq!(cursor.gaps(), 0);
+        cursor.insert(u32::MAX, 51);
+        assert_eq!(cursor.ranges(), [(0, u32::MAX)]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::atomic::with_suffix;
+use crate::config::Config;
+use crate::create_match::create_matches;
+use crate::saiblo::Saiblo;
+use crate::stats::Stats;
+use crate::storage::Storage;
+use color_eyre::eyre::{bail, Result, WrapErr};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::fs::{File, TryLockError};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How often a pending SIGTERM is noticed while waiting for the next cycle.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Locks `<storage>.lock` until the returned file is dropped, failing if another instance holds
+/// it.
+pub fn lock(storage: &Path) -> Result<File> {
+    let path = with_suffix(storage, ".lock");
+    let file =
+        File::create(&path).wrap_err_with(|| format!("failed to create {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => {
+            bail!("another instance is using {}", storage.display())
+        }
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Fetches, rates and reports, and schedules new matches, each on its own interval, keeping
+/// `stats` in memory between cycles. Returns after the current cycle on SIGTERM or SIGINT.
+///
+/// Errors of a cycle are printed and retried in the next one.
+pub fn run(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    saiblo: &dyn Saiblo,
+    config: &Config,
+) -> Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&stop))?;
+    }
+
+    let mut next_fetch = Instant::now();
+    let mut next_schedule = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= next_fetch {
+            next_fetch = now + config.daemon.fetch_interval();
+            if let Err(e) = crate::fetch_all(stats, storage, saiblo, config, 100) {
+                eprintln!("Failed to fetch matches:\n{e:?}");
+            }
+        }
+        if now >= next_schedule && !stop.load(Ordering::Relaxed) {
+            next_schedule = now + config.daemon.schedule_interval();
+            if let Err(e) = create_matches(stats, saiblo, config, None, false) {
+                eprintln!("Failed to create matches:\n{e:?}");
+            }
+        }
+
+        let next = next_fetch.min(next_schedule);
+        while !stop.load(Ordering::Relaxed) && Instant::now() < next {
+            sleep(POLL_INTERVAL.min(next.saturating_duration_since(Instant::now())));
+        }
+    }
+    println!("Stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_lock() {
+        let dir = std::env::temp_dir().join(format!("rollman-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = dir.join("storage.json");
+
+        let held = lock(&storage).unwrap();
+        assert!(lock(&storage).is_err());
+        drop(held);
+        assert!(lock(&storage).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,542 @@
+//! Export and import of the match list and the agent table, so the data can be analysed with
+//! other tools and a new instance can be seeded from someone else's dump.
+//!
+//! A dump is a directory with `matches.<ext>` and `agents.<ext>`. Both include archived seasons,
+//! told apart by `logic_version`. Draws and failures are not part of it.
+
+use crate::atomic::AtomicFile;
+use crate::season::{Season, SeasonAgent};
+use crate::stats::*;
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Csv,
+    JsonLines,
+    /// Only available with the `parquet` feature.
+    Parquet,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::JsonLines),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(format!("unknown dataset format: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchRecord {
+    pub id: u32,
+    pub logic_version: u16,
+    pub rollman: String,
+    pub ghost: String,
+    pub rollman_score: i16,
+    pub ghost_score: i16,
+    pub created_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub room_id: Option<u32>,
+    pub creator: Option<String>,
+}
+
+/// An agent in one logic version. Ratings and counts are informative only, since they are
+/// recomputed from the matches on import.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentRecord {
+    pub logic_version: u16,
+    pub token: String,
+    pub user: String,
+    pub name: String,
+    pub version: u32,
+    pub rollman_elo: f32,
+    pub ghost_elo: f32,
+    pub rollman_count: u32,
+    pub ghost_count: u32,
+    pub failure_count: u32,
+}
+
+trait Record: Serialize + DeserializeOwned {
+    const NAME: &'static str;
+
+    #[cfg(feature = "parquet")]
+    fn to_batch(records: &[Self]) -> Result<arrow_array::RecordBatch>;
+
+    #[cfg(feature = "parquet")]
+    fn from_batch(batch: &arrow_array::RecordBatch) -> Result<Vec<Self>>;
+}
+
+/// `(name, array)` of the field `$field` of all `$records`, which is nullable if `optional`.
+#[cfg(feature = "parquet")]
+macro_rules! column {
+    ($records:expr, $field:ident, $array:ty) => {
+        (
+            stringify!($field),
+            std::sync::Arc::new(<$array>::from_iter_values(
+                $records.iter().map(|r| r.$field.clone()),
+            )) as ArrayRef,
+        )
+    };
+    ($records:expr, $field:ident, $array:ty, optional) => {
+        (
+            stringify!($field),
+            std::sync::Arc::new(<$array>::from_iter(
+                $records.iter().map(|r| r.$field.clone()),
+            )) as ArrayRef,
+        )
+    };
+}
+
+impl Record for MatchRecord {
+    const NAME: &'static str = "matches";
+
+    #[cfg(feature = "parquet")]
+    fn to_batch(records: &[Self]) -> Result<arrow_array::RecordBatch> {
+        use arrow_array::*;
+        Ok(RecordBatch::try_from_iter([
+            column!(records, id, UInt32Array),
+            column!(records, logic_version, UInt16Array),
+            column!(records, rollman, StringArray),
+            column!(records, ghost, StringArray),
+            column!(records, rollman_score, Int16Array),
+            column!(records, ghost_score, Int16Array),
+            timestamp_column(records, "created_at", |r| r.created_at),
+            timestamp_column(records, "finished_at", |r| r.finished_at),
+            column!(records, room_id, UInt32Array, optional),
+            column!(records, creator, StringArray, optional),
+        ])?)
+    }
+
+    #[cfg(feature = "parquet")]
+    fn from_batch(batch: &arrow_array::RecordBatch) -> Result<Vec<Self>> {
+        use arrow_array::*;
+        let id = parquet_io::column::<UInt32Array>(batch, "id")?;
+        let logic_version = parquet_io::column::<UInt16Array>(batch, "logic_version")?;
+        let rollman = parquet_io::column::<StringArray>(batch, "rollman")?;
+        let ghost = parquet_io::column::<StringArray>(batch, "ghost")?;
+        let rollman_score = parquet_io::column::<Int16Array>(batch, "rollman_score")?;
+        let ghost_score = parquet_io::column::<Int16Array>(batch, "ghost_score")?;
+        let created_at = parquet_io::column::<TimestampMillisecondArray>(batch, "created_at")?;
+        let finished_at = parquet_io::column::<TimestampMillisecondArray>(batch, "finished_at")?;
+        let room_id = parquet_io::column::<UInt32Array>(batch, "room_id")?;
+        let creator = parquet_io::column::<StringArray>(batch, "creator")?;
+        let time = |array: &TimestampMillisecondArray, i| {
+            DateTime::from_timestamp_millis(array.is_valid(i).then(|| array.value(i))?)
+        };
+        Ok((0..batch.num_rows())
+            .map(|i| Self {
+                id: id.value(i),
+                logic_version: logic_version.value(i),
+                rollman: rollman.value(i).to_string(),
+                ghost: ghost.value(i).to_string(),
+                rollman_score: rollman_score.value(i),
+                ghost_score: ghost_score.value(i),
+                created_at: time(created_at, i),
+                finished_at: time(finished_at, i),
+                room_id: room_id.is_valid(i).then(|| room_id.value(i)),
+                creator: creator.is_valid(i).then(|| creator.value(i).to_string()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn timestamp_column<'a>(
+    records: &[MatchRecord],
+    name: &'a str,
+    field: impl Fn(&MatchRecord) -> Option<DateTime<Utc>>,
+) -> (&'a str, arrow_array::ArrayRef) {
+    let array = arrow_array::TimestampMillisecondArray::from_iter(
+        records
+            .iter()
+            .map(|r| field(r).map(|time| time.timestamp_millis())),
+    );
+    (name, std::sync::Arc::new(array.with_timezone_utc()))
+}
+
+impl Record for AgentRecord {
+    const NAME: &'static str = "agents";
+
+    #[cfg(feature = "parquet")]
+    fn to_batch(records: &[Self]) -> Result<arrow_array::RecordBatch> {
+        use arrow_array::*;
+        Ok(RecordBatch::try_from_iter([
+            column!(records, logic_version, UInt16Array),
+            column!(records, token, StringArray),
+            column!(records, user, StringArray),
+            column!(records, name, StringArray),
+            column!(records, version, UInt32Array),
+            column!(records, rollman_elo, Float32Array),
+            column!(records, ghost_elo, Float32Array),
+            column!(records, rollman_count, UInt32Array),
+            column!(records, ghost_count, UInt32Array),
+            column!(records, failure_count, UInt32Array),
+        ])?)
+    }
+
+    #[cfg(feature = "parquet")]
+    fn from_batch(batch: &arrow_array::RecordBatch) -> Result<Vec<Self>> {
+        use arrow_array::*;
+        let logic_version = parquet_io::column::<UInt16Array>(batch, "logic_version")?;
+        let token = parquet_io::column::<StringArray>(batch, "token")?;
+        let user = parquet_io::column::<StringArray>(batch, "user")?;
+        let name = parquet_io::column::<StringArray>(batch, "name")?;
+        let version = parquet_io::column::<UInt32Array>(batch, "version")?;
+        let rollman_elo = parquet_io::column::<Float32Array>(batch, "rollman_elo")?;
+        let ghost_elo = parquet_io::column::<Float32Array>(batch, "ghost_elo")?;
+        let rollman_count = parquet_io::column::<UInt32Array>(batch, "rollman_count")?;
+        let ghost_count = parquet_io::column::<UInt32Array>(batch, "ghost_count")?;
+        let failure_count = parquet_io::column::<UInt32Array>(batch, "failure_count")?;
+        Ok((0..batch.num_rows())
+            .map(|i| Self {
+                logic_version: logic_version.value(i),
+                token: token.value(i).to_string(),
+                user: user.value(i).to_string(),
+                name: name.value(i).to_string(),
+                version: version.value(i),
+                rollman_elo: rollman_elo.value(i),
+                ghost_elo: ghost_elo.value(i),
+                rollman_count: rollman_count.value(i),
+                ghost_count: ghost_count.value(i),
+                failure_count: failure_count.value(i),
+            })
+            .collect())
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_io {
+    use super::Record;
+    use crate::atomic::AtomicFile;
+    use arrow_array::{Array, RecordBatch};
+    use color_eyre::eyre::{OptionExt, Result};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::arrow::ArrowWriter;
+    use std::fs::File;
+
+    pub fn column<'a, A: Array + 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a A> {
+        batch
+            .column_by_name(name)
+            .and_then(|c| c.as_any().downcast_ref::<A>())
+            .ok_or_eyre(format!("missing or invalid column: {name}"))
+    }
+
+    pub fn write<T: Record>(file: AtomicFile, records: &[T]) -> Result<()> {
+        let batch = T::to_batch(records)?;
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.into_inner()?.commit()
+    }
+
+    pub fn read<T: Record>(file: File) -> Result<Vec<T>> {
+        let mut records = Vec::new();
+        for batch in ParquetRecordBatchReaderBuilder::try_new(file)?.build()? {
+            records.extend(T::from_batch(&batch?)?);
+        }
+        Ok(records)
+    }
+}
+
+fn write_records<T: Record>(dir: &Path, format: Format, records: &[T]) -> Result<()> {
+    let mut file = AtomicFile::create(dir.join(format!("{}.{}", T::NAME, format.extension())))?;
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.into_inner().map_err(|e| e.into_error())?.commit()
+        }
+        Format::JsonLines => {
+            for record in records {
+                serde_json::to_writer(&mut file, record)?;
+                writeln!(file)?;
+            }
+            file.commit()
+        }
+        #[cfg(feature = "parquet")]
+        Format::Parquet => parquet_io::write(file, records),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => color_eyre::eyre::bail!("built without the parquet feature"),
+    }
+}
+
+fn read_records<T: Record>(dir: &Path, format: Format) -> Result<Vec<T>> {
+    let file = File::open(dir.join(format!("{}.{}", T::NAME, format.extension())))?;
+    match format {
+        Format::Csv => Ok(csv::Reader::from_reader(file)
+            .deserialize()
+            .collect::<Result<_, _>>()?),
+        Format::JsonLines => BufReader::new(file)
+            .lines()
+            .filter(|line| line.as_ref().is_ok_and(|line| !line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect(),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => parquet_io::read(file),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => color_eyre::eyre::bail!("built without the parquet feature"),
+    }
+}
+
+impl Stats {
+    /// The matches and agents of the current logic version and all archived seasons.
+    pub fn to_records(&self) -> (Vec<MatchRecord>, Vec<AgentRecord>) {
+        let mut matches = Vec::new();
+        let mut agents = Vec::new();
+
+        let all_matches = self
+            .seasons
+            .values()
+            .flat_map(|season| &season.matches)
+            .chain(&self.matches);
+        for (id, m) in all_matches {
+            matches.push(MatchRecord {
+                id: *id,
+                logic_version: m.logic_version,
+                rollman: m.rollman.clone(),
+                ghost: m.ghost.clone(),
+                rollman_score: m.rollman_score,
+                ghost_score: m.ghost_score,
+                created_at: m.created_at,
+                finished_at: m.finished_at,
+                room_id: m.room_id,
+                creator: m.creator.clone(),
+            });
+        }
+
+        for (logic_version, season) in &self.seasons {
+            let mut season_agents = season.agents.iter().collect::<Vec<_>>();
+            season_agents.sort_unstable_by_key(|(token, _)| *token);
+            agents.extend(season_agents.into_iter().map(|(token, a)| AgentRecord {
+                logic_version: *logic_version,
+                token: token.clone(),
+                user: a.user.clone(),
+                name: a.name.clone(),
+                version: a.version,
+                rollman_elo: a.rollman_elo,
+                ghost_elo: a.ghost_elo,
+                rollman_count: a.rollman_count as u32,
+                ghost_count: a.ghost_count as u32,
+                failure_count: a.failure_count as u32,
+            }));
+        }
+        let mut current = self.agents.iter().collect::<Vec<_>>();
+        current.sort_unstable_by_key(|(token, _)| *token);
+        agents.extend(current.into_iter().map(|(token, a)| AgentRecord {
+            logic_version: self.logic_version,
+            token: token.clone(),
+            user: a.user.clone(),
+            name: a.name.clone(),
+            version: a.version,
+            rollman_elo: a.rollman_elo,
+            ghost_elo: a.ghost_elo,
+            rollman_count: a.rollman_count as u32,
+            ghost_count: a.ghost_count as u32,
+            failure_count: a.failure.len() as u32,
+        }));
+
+        (matches, agents)
+    }
+
+    /// Merges a dump into the data and returns how many matches were added. The result still
+    /// needs to be saved.
+    ///
+    /// If the dump has a newer logic version, the current one is archived first, as if the new
+    /// version had been fetched. Matches of the current version are added unless already known.
+    /// Seasons are only added as a whole if they are missing here. Everything else is skipped.
+    pub fn merge_records(
+        &mut self,
+        storage: &mut dyn Storage,
+        matches: Vec<MatchRecord>,
+        agents: Vec<AgentRecord>,
+    ) -> Result<usize> {
+        let Some(newest) = matches.iter().map(|m| m.logic_version).max() else {
+            return Ok(0);
+        };
+        if newest > self.logic_version {
+            let old_version = self.logic_version;
+            self.archive(newest);
+            storage.archive(old_version, self)?;
+        }
+
+        let mut new_seasons = BTreeMap::<u16, Season>::new();
+        for a in agents {
+            if a.logic_version == self.logic_version {
+                self.add_agent(&a.token, a.user, a.name, a.version);
+            } else if !self.seasons.contains_key(&a.logic_version) {
+                let agent = SeasonAgent {
+                    user: a.user,
+                    name: a.name,
+                    version: a.version,
+                    rollman_elo: a.rollman_elo,
+                    ghost_elo: a.ghost_elo,
+                    rollman_count: a.rollman_count as usize,
+                    ghost_count: a.ghost_count as usize,
+                    failure_count: a.failure_count as usize,
+                };
+                new_seasons
+                    .entry(a.logic_version)
+                    .or_default()
+                    .agents
+                    .insert(a.token, agent);
+            }
+        }
+
+        let mut count = 0;
+        for m in matches {
+            let (id, logic_version) = (m.id, m.logic_version);
+            let m = Match {
+                rollman: m.rollman,
+                ghost: m.ghost,
+                rollman_score: m.rollman_score,
+                ghost_score: m.ghost_score,
+                logic_version,
+                created_at: m.created_at,
+                finished_at: m.finished_at,
+                room_id: m.room_id,
+                creator: m.creator,
+            };
+            if logic_version == self.logic_version {
+                if self.contains(id)
+                    || !self.agents.contains_key(&m.rollman)
+                    || !self.agents.contains_key(&m.ghost)
+                {
+                    continue;
+                }
+                self.add_match(id, m);
+                count += 1;
+            } else if let Some(season) = new_seasons.get_mut(&logic_version) {
+                season.matches.insert(id, m);
+                count += 1;
+            }
+        }
+
+        self.seasons.extend(new_seasons);
+
+        Ok(count)
+    }
+}
+
+pub fn export(stats: &Stats, dir: impl AsRef<Path>, format: Format) -> Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let (matches, agents) = stats.to_records();
+    write_records(dir, format, &matches)?;
+    write_records(dir, format, &agents)
+}
+
+/// Returns how many matches were added. The result still needs to be saved.
+pub fn import(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    dir: impl AsRef<Path>,
+    format: Format,
+) -> Result<usize> {
+    let dir = dir.as_ref();
+    let matches = read_records(dir, format)?;
+    let agents = read_records(dir, format)?;
+    stats.merge_records(storage, matches, agents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonStorage;
+
+    fn sample() -> Stats {
+        let mut stats = Stats {
+            logic_version: 4,
+            ..Default::default()
+        };
+        for token in ["a", "b", "c"] {
+            let name = "bot, \"quoted\"".to_string();
+            stats.add_agent(token, format!("user-{token}"), name, 1);
+        }
+        let m = |rollman: &str, ghost: &str, rollman_score, ghost_score, logic_version| Match {
+            rollman: rollman.to_string(),
+            ghost: ghost.to_string(),
+            rollman_score,
+            ghost_score,
+            logic_version,
+            created_at: (logic_version == 5).then(|| "2025-02-01T12:00:00Z".parse().unwrap()),
+            finished_at: None,
+            room_id: (logic_version == 5).then_some(42),
+            creator: (logic_version == 5).then(|| "admin".to_string()),
+        };
+        stats.add_match(1, m("a", "b", 10, 3, 4));
+        stats.archive(5);
+        for token in ["a", "c"] {
+            stats.add_agent(token, format!("user-{token}"), "bot".to_string(), 2);
+        }
+        stats.add_match(2, m("a", "c", 7, 8, 5));
+        stats.add_match(3, m("c", "a", 9, 1, 5));
+        stats
+    }
+
+    #[test]
+    fn round_trip() {
+        let stats = sample();
+        let (matches, agents) = stats.to_records();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(agents.len(), 4);
+
+        let mut formats = vec![Format::Csv, Format::JsonLines];
+        if cfg!(feature = "parquet") {
+            formats.push(Format::Parquet);
+        }
+        for format in formats {
+            let dir = std::env::temp_dir().join(format!(
+                "rollman-elo-dataset-{}-{}",
+                std::process::id(),
+                format.extension()
+            ));
+            export(&stats, &dir, format).unwrap();
+            assert_eq!(read_records::<MatchRecord>(&dir, format).unwrap(), matches);
+            assert_eq!(read_records::<AgentRecord>(&dir, format).unwrap(), agents);
+
+            let mut imported = Stats::default();
+            let mut storage = JsonStorage::new(dir.join("storage.json"), 0);
+            assert_eq!(
+                import(&mut imported, &mut storage, &dir, format).unwrap(),
+                3
+            );
+            assert_eq!(imported.logic_version, 5);
+            assert_eq!(imported.matches.len(), 2);
+            assert_eq!(imported.matches[&2].room_id, Some(42));
+            assert_eq!(imported.agents.len(), 2);
+            assert_eq!(imported.seasons[&4].matches.len(), 1);
+            assert_eq!(imported.seasons[&4].agents["b"].name, "bot, \"quoted\"");
+
+            // Importing again adds nothing.
+            assert_eq!(
+                import(&mut imported, &mut storage, &dir, format).unwrap(),
+                0
+            );
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
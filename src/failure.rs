@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    Rollman,
+    Ghost,
+}
+
+impl Role {
+    /// The role of `side` of Saiblo's `info`, given the side that played the rollman.
+    pub fn from_side(side: usize, rollman_side: usize) -> Self {
+        if side == rollman_side {
+            Self::Rollman
+        } else {
+            Self::Ghost
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rollman => write!(f, "rollman"),
+            Self::Ghost => write!(f, "ghost"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FailureKind {
+    TimeLimit,
+    RuntimeError,
+    MemoryLimit,
+    OutputLimit,
+    IllegalOutput,
+    /// An `end_state` not known to us, kept verbatim.
+    Other(String),
+    /// Recorded before failure kinds were stored.
+    Unknown,
+}
+
+impl FailureKind {
+    pub fn from_end_state(end_state: Option<&str>) -> Self {
+        match end_state {
+            Some("TLE" | "STLE") => Self::TimeLimit,
+            Some("RE" | "EXIT") => Self::RuntimeError,
+            Some("MLE") => Self::MemoryLimit,
+            Some("OLE") => Self::OutputLimit,
+            Some("IA" | "IOE" | "ILLEGAL") => Self::IllegalOutput,
+            Some(other) => Self::Other(other.to_string()),
+            None => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimeLimit => write!(f, "TLE"),
+            Self::RuntimeError => write!(f, "RE"),
+            Self::MemoryLimit => write!(f, "MLE"),
+            Self::OutputLimit => write!(f, "OLE"),
+            Self::IllegalOutput => write!(f, "IA"),
+            Self::Other(s) => write!(f, "{s}"),
+            Self::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+/// How a failure against an opponent that ended normally affects the rating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// Failures only affect eligibility for the ranking.
+    Ignore,
+    /// The failure loses to every other agent that played the same opponent in the same role.
+    Loss,
+    /// The failure counts as the worst score any agent of the same role got against the
+    /// opponent, so it ties with that score and loses to all others.
+    WorstScore,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub kind: FailureKind,
+    pub role: Option<Role>,
+    /// Token of the opponent, which ended normally.
+    pub opponent: Option<String>,
+}
+
+/// e.g. "TLE 3, RE 1"
+pub fn failure_breakdown<'a>(failures: impl IntoIterator<Item = &'a Failure>) -> String {
+    let mut counts = BTreeMap::new();
+    for failure in failures {
+        *counts.entry(&failure.kind).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(kind, count)| format!("{kind} {count}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,62 +1,262 @@
-use crate::constants::*;
+use crate::analytics::MatchAnalytics;
+use crate::failure::*;
+use crate::replay::FrameReader;
+use crate::replay_cache::ReplayCache;
+use crate::saiblo::{MatchInfo, Saiblo, JUDGING, WAITING};
 use crate::stats::*;
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::Result;
-use serde::Deserialize;
-use std::io::{BufRead, BufReader};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-const PAGE_SIZE: usize = 20;
+fn parse_time(time: Option<&str>) -> Option<DateTime<Utc>> {
+    Some(DateTime::parse_from_rfc3339(time?).ok()?.to_utc())
+}
 
-#[derive(Deserialize)]
-struct Response {
-    results: Vec<MatchInfo>,
+/// `value[key]` if `value` is an object, `value` itself otherwise.
+fn field<'a>(value: &'a Value, key: &str) -> &'a Value {
+    value.get(key).unwrap_or(value)
+}
+
+/// The ids of the matches in `results` that `fetch` will read the replay of, as far as it can be
+/// told before any of them is added.
+fn replays_needed(stats: &Stats, results: &[MatchInfo]) -> Vec<u32> {
+    let mut logic_version = stats.logic_version;
+    let mut ids = Vec::new();
+    for result in results {
+        if is_pending(result) || result.state == "评测失败" {
+            continue;
+        }
+        let Some(version) = result.logic_version else {
+            continue;
+        };
+        if version < logic_version {
+            break;
+        }
+        logic_version = version;
+        if stats.contains(result.id) {
+            continue;
+        }
+        let (info0, info1) = &result.info;
+        if info0.code.is_some() && info1.code.is_some() && info0.score != info1.score {
+            ids.push(result.id);
+        }
+    }
+    ids
+}
+
+/// Which side of Saiblo's `info` played the rollman. Saiblo's order of the players is not the
+/// role order, so the sides are matched to the final score of the replay, which is. `None` if
+/// the scores are equal, as either side would match, or if neither does.
+///
+/// Ties and failures with equal scores therefore have unknown roles, and need no replay.
+fn rollman_side_of(
+    (score0, score1): (i16, i16),
+    (rollman_score, ghost_score): (i16, i16),
+) -> Option<usize> {
+    if score0 == score1 {
+        None
+    } else if (score0, score1) == (rollman_score, ghost_score) {
+        Some(0)
+    } else if (score1, score0) == (rollman_score, ghost_score) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Reads the replays from the cache, and downloads the missing ones with up to `workers` requests
+/// at once. Download errors are returned per replay.
+fn load_replays(
+    cache: &ReplayCache,
+    saiblo: &dyn Saiblo,
+    ids: &[u32],
+    workers: usize,
+) -> Result<HashMap<u32, Result<Vec<u8>>>> {
+    let mut replays = HashMap::new();
+    let mut missing = Vec::new();
+    for &id in ids {
+        match cache.get(id)? {
+            Some(replay) => {
+                replays.insert(id, Ok(replay));
+            }
+            None => missing.push(id),
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let downloaded = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1).min(missing.len()) {
+            scope.spawn(|| {
+                while let Some(&id) = missing.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let replay = saiblo.download_replay(id);
+                    downloaded.lock().unwrap().push((id, replay));
+                }
+            });
+        }
+    });
+
+    for (id, replay) in downloaded.into_inner().unwrap() {
+        if let Ok(replay) = &replay {
+            cache.insert(id, replay)?;
+        }
+        replays.insert(id, replay);
+    }
+    Ok(replays)
 }
 
-#[derive(Deserialize)]
-struct MatchInfo {
-    id: u32,
-    logic_version: Option<u16>,
-    state: String,
-    info: (AgentInfo, AgentInfo),
+/// Lists matches newest first and adds the new ones, until everything below has been listed
+/// before as recorded by `stats.fetch_cursor`, skipping the listed ranges in between. Lists at most
+/// `max_pages` pages, then polls the pending matches that were not listed, and returns the number
+/// of gaps left in the listed history.
+pub fn fetch(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    cache: &ReplayCache,
+    saiblo: &dyn Saiblo,
+    page_size: usize,
+    max_pages: usize,
+    download_workers: usize,
+) -> Result<usize> {
+    let mut listed = HashSet::new();
+    let mut offset = 0;
+    // The oldest match of the previous page, if the list is contiguous from it to `offset`.
+    let mut previous = None;
+    for _ in 0..max_pages {
+        let list = saiblo.list_matches(offset, page_size)?;
+        let (Some(newest), Some(oldest)) = (list.results.first(), list.results.last()) else {
+            // The end of the list. An empty first page says nothing about what is listed.
+            if let Some(previous) = previous {
+                stats.fetch_cursor.insert(0, previous);
+                save_progress(stats, storage)?;
+            }
+            break;
+        };
+        let (newest, oldest) = (newest.id, oldest.id);
+        let len = list.results.len();
+        listed.extend(list.results.iter().map(|m| m.id));
+
+        let older_version = !fetch_page(
+            stats,
+            storage,
+            cache,
+            saiblo,
+            list.results,
+            download_workers,
+        )?;
+        // Nothing older is needed after the end of the list or of the logic version.
+        let end = older_version || len < page_size;
+        stats.fetch_cursor.insert(
+            if end { 0 } else { oldest },
+            previous.map_or(newest, |previous: u32| previous.max(newest)),
+        );
+        save_progress(stats, storage)?;
+        if end {
+            break;
+        }
+
+        offset += len;
+        previous = Some(oldest);
+        if let Some((start, _)) = stats.fetch_cursor.range_containing(oldest) {
+            if start == 0 {
+                break;
+            }
+            // Skips to the first match below the listed range.
+            offset = offset_below(saiblo, start, offset, list.count)?;
+            previous = Some(start);
+        }
+    }
+
+    poll_pending(stats, storage, cache, saiblo, &listed, download_workers)?;
+    Ok(stats.fetch_cursor.gaps())
 }
 
-#[derive(Deserialize)]
-struct AgentInfo {
-    code: Option<CodeInfo>,
-    user: UserInfo,
-    score: i16,
-    end_state: Option<String>,
+/// Saves what `fetch` has listed so far. The pending matches are saved along with the cursor, as
+/// the cursor assumes they are tracked.
+fn save_progress(stats: &Stats, storage: &mut dyn Storage) -> Result<()> {
+    storage.save_pending(&stats.pending)?;
+    storage.save_cursor(&stats.fetch_cursor)
 }
 
-#[derive(Deserialize)]
-struct CodeInfo {
-    id: String,
-    entity: String,
-    version: u32,
+/// Gets the pending matches other than `listed` one by one, and adds the finished ones.
+fn poll_pending(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    cache: &ReplayCache,
+    saiblo: &dyn Saiblo,
+    listed: &HashSet<u32>,
+    download_workers: usize,
+) -> Result<()> {
+    let mut results = Vec::new();
+    for &id in stats.pending.keys().rev() {
+        if listed.contains(&id) {
+            continue;
+        }
+        match saiblo.get_match(id) {
+            Ok(result) => results.push(result),
+            Err(e) => eprintln!("Failed to poll match {id}:\n{e:?}"),
+        }
+    }
+    // Matches of an older logic version stop `fetch_page`, and are not needed anyway.
+    results.retain(|result| {
+        let older = !is_pending(result)
+            && result
+                .logic_version
+                .is_some_and(|version| version < stats.logic_version);
+        if older {
+            stats.pending.remove(&result.id);
+        }
+        !older
+    });
+    fetch_page(stats, storage, cache, saiblo, results, download_workers)?;
+    save_progress(stats, storage)
 }
 
-#[derive(Deserialize)]
-struct UserInfo {
-    username: String,
+fn is_pending(result: &MatchInfo) -> bool {
+    result.state == JUDGING || result.state == WAITING
 }
 
-#[derive(Deserialize)]
-struct ReplayLine {
-    score: (i16, i16),
+/// The offset of the newest match with an id below `id`, searched in `lo..hi`.
+fn offset_below(saiblo: &dyn Saiblo, id: u32, mut lo: usize, mut hi: usize) -> Result<usize> {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match saiblo.list_matches(mid, 1)?.results.first() {
+            Some(m) if m.id >= id => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    Ok(lo)
 }
 
-pub fn fetch(stats: &mut Stats, awaiting: u32, page: usize) -> Result<bool> {
-    let req = ureq::get(format!("{BASE_URL}/matches/"))
-        .query("limit", PAGE_SIZE.to_string())
-        .query("offset", (page * PAGE_SIZE).to_string())
-        .query("game", GAME_ID.to_string())
-        .header(TOKEN_HEADER, &*TOKEN);
-    let res: Response = req.call()?.into_body().read_json()?;
-
-    for result in res.results {
-        if result.state == "评测中" || result.state == "准备中" {
-            stats.awaiting = result.id;
+/// Adds the new matches of a page. Returns `false` if a match of an older logic version is found,
+/// as all following ones are older too.
+fn fetch_page(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    cache: &ReplayCache,
+    saiblo: &dyn Saiblo,
+    results: Vec<MatchInfo>,
+    download_workers: usize,
+) -> Result<bool> {
+    let mut replays = load_replays(
+        cache,
+        saiblo,
+        &replays_needed(stats, &results),
+        download_workers,
+    )?;
+
+    let now = Utc::now();
+    for result in results {
+        if is_pending(&result) {
+            stats.pending.entry(result.id).or_insert(now);
             continue;
         }
+        let first_seen = stats.pending.remove(&result.id);
         if result.state == "评测失败" {
             continue;
         }
@@ -68,90 +268,421 @@ pub fn fetch(stats: &mut Stats, awaiting: u32, page: usize) -> Result<bool> {
         if logic_version < stats.logic_version {
             return Ok(false);
         }
-        if stats.matches.contains_key(&result.id) {
-            if result.id < awaiting {
-                return Ok(false);
-            }
+        if stats.contains(result.id) {
             continue;
         }
         let (code0, code1) = match (result.info.0.code, result.info.1.code) {
             (Some(code0), Some(code1)) => (code0, code1),
             _ => continue,
         };
-        if result.info.0.score == result.info.1.score
-            || result.info.0.end_state != Some("OK".to_string())
-            || result.info.1.end_state != Some("OK".to_string())
-        {
-            if result.info.0.end_state != Some("OK".to_string())
-                && result.info.1.end_state == Some("OK".to_string())
-            {
-                if let Some(agent) = stats.agents.get_mut(&code0.id) {
-                    agent.failure.insert(result.id);
+        let replay = if result.info.0.score != result.info.1.score {
+            Some(match replays.remove(&result.id) {
+                Some(replay) => replay,
+                None => load_replays(cache, saiblo, &[result.id], 1)?
+                    .remove(&result.id)
+                    .unwrap(),
+            })
+        } else {
+            None
+        };
+        let analytics = match &replay {
+            Some(Ok(replay)) => {
+                let mut error = None;
+                let frames = FrameReader::new(replay.as_slice())
+                    .map_while(|frame| frame.map_err(|e| error = Some(e)).ok());
+                let analytics = MatchAnalytics::from_frames(frames);
+                if let Some(e) = error {
+                    eprintln!(
+                        "Skipping match {} with an invalid replay:\n{e:?}",
+                        result.id
+                    );
+                    continue;
                 }
+                analytics
             }
-            if result.info.1.end_state != Some("OK".to_string())
-                && result.info.0.end_state == Some("OK".to_string())
-            {
-                if let Some(agent) = stats.agents.get_mut(&code1.id) {
-                    agent.failure.insert(result.id);
-                }
+            _ => None,
+        };
+        let scores = (result.info.0.score, result.info.1.score);
+        let rollman_side = analytics
+            .as_ref()
+            .and_then(|analytics| rollman_side_of(scores, analytics.final_score()));
+        let role = |side| rollman_side.map(|rollman| Role::from_side(side, rollman));
+
+        let ok0 = result.info.0.end_state.as_deref() == Some("OK");
+        let ok1 = result.info.1.end_state.as_deref() == Some("OK");
+        if !ok0 && ok1 {
+            let failure = Failure {
+                kind: FailureKind::from_end_state(result.info.0.end_state.as_deref()),
+                role: role(0),
+                opponent: Some(code1.id),
+            };
+            if stats.add_failure(result.id, &code0.id, failure.clone()) {
+                storage.insert_failure(result.id, &code0.id, &failure)?;
+            }
+            continue;
+        }
+        if !ok1 && ok0 {
+            let failure = Failure {
+                kind: FailureKind::from_end_state(result.info.1.end_state.as_deref()),
+                role: role(1),
+                opponent: Some(code0.id),
+            };
+            if stats.add_failure(result.id, &code1.id, failure.clone()) {
+                storage.insert_failure(result.id, &code1.id, &failure)?;
             }
             continue;
         }
         if logic_version > stats.logic_version {
-            stats.clear();
-            stats.logic_version = logic_version;
+            let old_version = stats.logic_version;
+            // Newer matches may already be pending, and their page is about to be marked listed.
+            let newer_pending = stats.pending.split_off(&result.id);
+            stats.archive(logic_version);
+            stats.pending = newer_pending;
+            storage.archive(old_version, stats)?;
         }
 
-        let replay_reader = match ureq::get(&format!("{BASE_URL}/matches/{}/download/", result.id))
-            .header(TOKEN_HEADER, &*TOKEN)
-            .call()
-        {
-            Ok(res) => res.into_body().into_reader(),
-            _ => continue,
-        };
-
-        let last_line = if let Some(line) = BufReader::new(replay_reader).lines().last() {
-            line?
-        } else {
-            continue;
-        };
-        let replay_line: ReplayLine = if let Ok(replay_line) = serde_json::from_str(&last_line) {
-            replay_line
-        } else {
-            continue;
-        };
-        let (rollman_score, ghost_score) = replay_line.score;
-
-        let (rollman, ghost) =
-            if result.info.0.score == rollman_score && result.info.1.score == ghost_score {
-                (code0.id.clone(), code1.id.clone())
-            } else if result.info.1.score == rollman_score && result.info.0.score == ghost_score {
-                (code1.id.clone(), code0.id.clone())
-            } else {
-                eprintln!("Invalid scores: {}", result.id);
-                continue;
-            };
-
-        stats.agents.entry(code0.id).or_insert(Agent::new(
+        stats.add_agent(
+            &code0.id,
             result.info.0.user.username,
             code0.entity,
             code0.version,
-        ));
-        stats.agents.entry(code1.id).or_insert(Agent::new(
+        );
+        stats.add_agent(
+            &code1.id,
             result.info.1.user.username,
             code1.entity,
             code1.version,
-        ));
+        );
+        storage.insert_agent(&code0.id, &stats.agents[&code0.id])?;
+        storage.insert_agent(&code1.id, &stats.agents[&code1.id])?;
+
+        if !ok0 || result.info.0.score == result.info.1.score {
+            let side0 = (code0.id, result.info.0.score, result.info.0.end_state);
+            let side1 = (code1.id, result.info.1.score, result.info.1.end_state);
+            let (rollman, ghost) = match rollman_side {
+                Some(1) => (side1, side0),
+                _ => (side0, side1),
+            };
+            let kind = if ok0 {
+                DrawKind::Tie
+            } else {
+                DrawKind::DoubleFailure {
+                    rollman: FailureKind::from_end_state(rollman.2.as_deref()),
+                    ghost: FailureKind::from_end_state(ghost.2.as_deref()),
+                }
+            };
+            let draw = Draw {
+                rollman: rollman.0,
+                ghost: ghost.0,
+                rollman_score: rollman.1,
+                ghost_score: ghost.1,
+                kind,
+                roles_known: rollman_side.is_some(),
+            };
+            storage.insert_draw(result.id, &draw)?;
+            stats.add_draw(result.id, draw);
+            continue;
+        }
+
+        if let Some(Err(e)) = &replay {
+            eprintln!("Skipping match {} without a replay:\n{e:?}", result.id);
+            // Downloaded again when polled.
+            stats.pending.insert(result.id, first_seen.unwrap_or(now));
+            continue;
+        }
+        let Some(analytics) = analytics else {
+            continue;
+        };
+        let (rollman_score, ghost_score) = analytics.final_score();
+        let (rollman, ghost) = match rollman_side {
+            Some(0) => (code0.id, code1.id),
+            Some(_) => (code1.id, code0.id),
+            None => {
+                eprintln!("Invalid scores: {}", result.id);
+                continue;
+            }
+        };
 
         let m = Match {
             rollman,
             ghost,
             rollman_score,
             ghost_score,
+            logic_version,
+            created_at: parse_time(result.create_time.as_deref()),
+            finished_at: parse_time(result.finish_time.as_deref()),
+            room_id: result
+                .room
+                .as_ref()
+                .and_then(|room| field(room, "id").as_u64())
+                .and_then(|id| u32::try_from(id).ok()),
+            creator: result
+                .creator
+                .as_ref()
+                .and_then(|creator| field(creator, "username").as_str())
+                .map(str::to_string),
         };
+        storage.insert_match(result.id, &m)?;
+        storage.insert_analytics(result.id, &analytics)?;
         stats.add_match(result.id, m);
+        stats.analytics.insert(result.id, analytics);
     }
 
     Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saiblo::fake::FakeSaiblo;
+    use crate::storage::JsonStorage;
+    use chrono::TimeDelta;
+    use std::path::PathBuf;
+
+    const REPLAY: &[u8] = include_bytes!("../tests/fixtures/replay.jsonl");
+
+    /// An empty replay cache and storage in a temporary directory, which the test removes.
+    fn temp_storage(name: &str) -> (PathBuf, ReplayCache, JsonStorage) {
+        let dir = std::env::temp_dir().join(format!("rollman-elo-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = ReplayCache::new(dir.join("replays"), false, u64::MAX).unwrap();
+        let storage = JsonStorage::new(dir.join("storage.json"), 0);
+        (dir, cache, storage)
+    }
+
+    #[test]
+    fn fetch_from_fake() {
+        let mut saiblo = FakeSaiblo::default();
+        saiblo.add_match(1, ("a", 7), ("b", 2), REPLAY);
+        saiblo.add_match(2, ("b", 7), ("a", 2), REPLAY);
+        // A tie.
+        saiblo.add_match(3, ("a", 1), ("b", 1), REPLAY);
+        saiblo.add_match(4, ("a", 0), ("b", 0), REPLAY);
+        saiblo.matches[0].state = WAITING.to_string();
+
+        let (dir, cache, mut storage) = temp_storage("fetch");
+        let mut stats = Stats::default();
+
+        let gaps = fetch(&mut stats, &mut storage, &cache, &saiblo, 20, 10, 2).unwrap();
+        assert_eq!(gaps, 0);
+        assert_eq!(stats.pending.keys().copied().collect::<Vec<_>>(), [4]);
+        assert_eq!(stats.matches.keys().copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(stats.matches[&2].rollman, "b");
+        assert_eq!(stats.draws.keys().copied().collect::<Vec<_>>(), [3]);
+        assert!(matches!(stats.draws[&3].kind, DrawKind::Tie) && !stats.draws[&3].roles_known);
+        assert_eq!(stats.agents["a"].user, "a-user");
+        assert_eq!(stats.analytics[&1].length, 4);
+        assert!(cache.get(1).unwrap().is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_replay() {
+        let mut truncated = REPLAY.to_vec();
+        truncated.extend_from_slice(b"{\"round\": 4, \"score\": [9,");
+        let mut saiblo = FakeSaiblo::default();
+        saiblo.add_match(1, ("a", 7), ("b", 2), REPLAY);
+        saiblo.add_match(2, ("a", 7), ("b", 2), &truncated);
+
+        let (dir, cache, mut storage) = temp_storage("invalid");
+        let mut stats = Stats::default();
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 20, 10, 1).unwrap();
+        assert_eq!(stats.matches.keys().copied().collect::<Vec<_>>(), [1]);
+        assert!(stats.pending.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_downloads_are_deterministic() {
+        let mut saiblo = FakeSaiblo::default();
+        let tokens = ["a", "b", "c", "d"];
+        for id in 1..=20 {
+            let rollman = tokens[id as usize % 4];
+            let ghost = tokens[(id as usize * 3 + 1) % 4];
+            saiblo.add_match(id, (rollman, 7), (ghost, 2), REPLAY);
+        }
+
+        let ratings = |workers| {
+            let (dir, cache, mut storage) = temp_storage("workers");
+            let mut stats = Stats::default();
+            fetch(&mut stats, &mut storage, &cache, &saiblo, 20, 10, workers).unwrap();
+            assert_eq!(stats.matches.len(), 20);
+            let ratings = tokens.map(|t| (stats.agents[t].rollman_elo, stats.agents[t].ghost_elo));
+            std::fs::remove_dir_all(dir).unwrap();
+            format!("{ratings:?}")
+        };
+        assert_eq!(ratings(1), ratings(8));
+    }
+
+    #[test]
+    fn backfill_resumes() {
+        let mut saiblo = FakeSaiblo::default();
+        for id in 1..=30 {
+            saiblo.add_match(id, ("a", 7), ("b", 2), REPLAY);
+        }
+
+        let (dir, cache, mut storage) = temp_storage("backfill");
+        let mut stats = Stats::default();
+
+        let gaps = fetch(&mut stats, &mut storage, &cache, &saiblo, 4, 2, 1).unwrap();
+        assert_eq!(gaps, 1);
+        assert_eq!(stats.fetch_cursor.ranges(), [(23, 30)]);
+
+        // New matches are listed first, then the history continues below what has been listed.
+        saiblo.add_match(31, ("a", 7), ("b", 2), REPLAY);
+        saiblo.add_match(32, ("a", 7), ("b", 2), REPLAY);
+        let gaps = fetch(&mut stats, &mut storage, &cache, &saiblo, 4, 2, 1).unwrap();
+        assert_eq!(gaps, 1);
+        assert_eq!(stats.fetch_cursor.ranges(), [(19, 32)]);
+
+        while fetch(&mut stats, &mut storage, &cache, &saiblo, 4, 2, 1).unwrap() > 0 {}
+        assert_eq!(stats.matches.len(), 32);
+        assert_eq!(stats.fetch_cursor.ranges(), [(0, 32)]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_first_run() {
+        let mut saiblo = FakeSaiblo::default();
+        let (dir, cache, mut storage) = temp_storage("empty");
+        let mut stats = Stats::default();
+
+        assert_eq!(
+            fetch(&mut stats, &mut storage, &cache, &saiblo, 4, 10, 1).unwrap(),
+            1
+        );
+        assert!(stats.fetch_cursor.ranges().is_empty());
+
+        for id in 1..=12 {
+            saiblo.add_match(id, ("a", 7), ("b", 2), REPLAY);
+        }
+        let gaps = fetch(&mut stats, &mut storage, &cache, &saiblo, 4, 10, 1).unwrap();
+        assert_eq!(gaps, 0);
+        assert_eq!(stats.matches.len(), 12);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pending_across_archive() {
+        let mut saiblo = FakeSaiblo::default();
+        for id in 1..=3 {
+            saiblo.add_match(id, ("a", 7), ("b", 2), REPLAY);
+        }
+        let (dir, cache, mut storage) = temp_storage("archive");
+        let mut stats = Stats::default();
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 10, 10, 1).unwrap();
+
+        for id in 4..=6 {
+            saiblo.add_match(id, ("a", 7), ("b", 2), REPLAY);
+            saiblo.matches[0].logic_version = Some(1);
+        }
+        saiblo.matches[0].state = WAITING.to_string();
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 10, 10, 1).unwrap();
+        assert_eq!(stats.logic_version, 1);
+        assert_eq!(stats.seasons[&0].matches.len(), 3);
+        assert_eq!(stats.matches.keys().copied().collect::<Vec<_>>(), [4, 5]);
+        assert_eq!(stats.pending.keys().copied().collect::<Vec<_>>(), [6]);
+
+        saiblo.matches[0].state = "评测成功".to_string();
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 10, 10, 1).unwrap();
+        assert!(stats.pending.is_empty());
+        assert_eq!(stats.matches.len(), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn poll_pending_matches() {
+        let mut saiblo = FakeSaiblo::default();
+        for id in 1..=6 {
+            saiblo.add_match(id, ("a", 7), ("b", 2), REPLAY);
+        }
+        saiblo.matches[4].state = JUDGING.to_string();
+        saiblo.replays.remove(&3);
+
+        let (dir, cache, mut storage) = temp_storage("pending");
+        let mut stats = Stats::default();
+
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 2, 10, 1).unwrap();
+        assert_eq!(stats.pending.keys().copied().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(stats.matches.len(), 4);
+        let first_seen = stats.pending[&3];
+        let later = first_seen + TimeDelta::minutes(30);
+        assert!(stats.stuck(later, TimeDelta::minutes(60)).is_empty());
+        assert_eq!(
+            stats.stuck(later, TimeDelta::minutes(10)),
+            [(2, stats.pending[&2]), (3, first_seen)]
+        );
+
+        // Only the newest page is listed again, the rest are polled.
+        saiblo.matches[4].state = "评测成功".to_string();
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 2, 10, 1).unwrap();
+        assert_eq!(stats.pending.keys().copied().collect::<Vec<_>>(), [3]);
+        assert_eq!(stats.pending[&3], first_seen);
+        assert!(stats.matches.contains_key(&2));
+
+        saiblo.replays.insert(3, REPLAY.to_vec());
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 2, 10, 1).unwrap();
+        assert!(stats.pending.is_empty());
+        assert_eq!(stats.matches.len(), 6);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failure_roles_from_replay() {
+        let mut saiblo = FakeSaiblo::default();
+        // Newest first, so the agents are known before their failures.
+        saiblo.add_match(3, ("a", 7), ("b", 2), REPLAY);
+        // Saiblo lists the ghost first, the replay ends 7:2.
+        saiblo.add_match(2, ("b", 2), ("a", 7), REPLAY);
+        saiblo.add_match(1, ("a", 0), ("b", 0), REPLAY);
+        saiblo.matches[1].info.0.end_state = Some("TLE".to_string());
+        saiblo.matches[2].info.1.end_state = Some("RE".to_string());
+
+        let (dir, cache, mut storage) = temp_storage("roles");
+        let mut stats = Stats::default();
+
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 20, 10, 1).unwrap();
+        let failure = &stats.agents["b"].failure[&2];
+        assert!(failure.kind == FailureKind::TimeLimit && failure.role == Some(Role::Ghost));
+        assert_eq!(failure.opponent.as_deref(), Some("a"));
+        assert!(stats.agents["b"].failure[&1].role.is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn draw_roles_from_replay() {
+        let mut saiblo = FakeSaiblo::default();
+        // Saiblo lists the ghost first, the replay ends 7:2.
+        saiblo.add_match(2, ("b", 2), ("a", 7), REPLAY);
+        saiblo.add_match(1, ("a", 1), ("b", 1), REPLAY);
+        saiblo.matches[0].info.0.end_state = Some("TLE".to_string());
+        saiblo.matches[0].info.1.end_state = Some("RE".to_string());
+
+        let (dir, cache, mut storage) = temp_storage("draws");
+        let mut stats = Stats::default();
+
+        fetch(&mut stats, &mut storage, &cache, &saiblo, 20, 10, 1).unwrap();
+        let draw = &stats.draws[&2];
+        assert!(draw.roles_known);
+        assert_eq!((draw.rollman.as_str(), draw.ghost.as_str()), ("a", "b"));
+        assert_eq!((draw.rollman_score, draw.ghost_score), (7, 2));
+        assert!(matches!(
+            &draw.kind,
+            DrawKind::DoubleFailure {
+                rollman: FailureKind::RuntimeError,
+                ghost: FailureKind::TimeLimit,
+            }
+        ));
+
+        assert!(!stats.draws[&1].roles_known);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,39 +1,123 @@
-mod constants;
+mod analytics;
+mod api;
+mod atomic;
+mod cli;
+mod config;
 mod create_match;
+mod cursor;
+mod daemon;
+mod dataset;
 mod elo;
+mod failure;
 mod fetch;
+mod migrate;
+mod replay;
+mod replay_cache;
+mod saiblo;
+mod score_stats;
+mod season;
 mod stats;
+mod storage;
+mod user;
 
-use chrono::{Local, Timelike};
+use chrono::{Local, Utc};
+use clap::Parser;
+use cli::{Cli, Command, Query};
 use color_eyre::eyre::Result;
+use config::Config;
 use create_match::create_matches;
+use replay_cache::ReplayCache;
+use saiblo::{Saiblo, SaibloClient};
 use stats::Stats;
-use std::thread::sleep;
+use storage::Storage;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let now = Local::now();
-    if matches!(now.hour(), 3 | 4) {
-        let next_five = now.with_hour(5).unwrap().with_minute(0).unwrap();
-        let duration = (next_five - now).to_std().unwrap();
-        sleep(duration);
-    }
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+    let command = cli.command.unwrap_or(Command::Run);
+
+    // Every command that writes to the storage holds the lock until it returns.
+    let _lock = match command {
+        Command::Run | Command::Daemon | Command::Fetch { .. } | Command::Import { .. } => {
+            Some(daemon::lock(&config.storage.path)?)
+        }
+        _ => None,
+    };
 
-    let mut stats = Stats::load()?;
+    let mut storage = storage::open(&config.storage)?;
+    let mut stats = Stats::load(&mut *storage, &config.rating)?;
+    let saiblo = SaibloClient::new(&config);
 
-    let mut page = 0;
-    let awaiting = stats
-        .awaiting
-        .min(stats.matches.last_key_value().map(|(k, _)| *k).unwrap_or(0));
-    stats.awaiting = u32::MAX;
-    while fetch::fetch(&mut stats, awaiting, page)? && page < 100 {
-        page += 1;
-        println!("Collected {} matches", stats.matches.len());
+    match command {
+        Command::Run => {
+            fetch_all(&mut stats, &mut *storage, &saiblo, &config, 100)?;
+            create_matches(&stats, &saiblo, &config, None, false)?;
+        }
+        Command::Daemon => daemon::run(&mut stats, &mut *storage, &saiblo, &config)?,
+        Command::Fetch { max_pages } => {
+            fetch_all(&mut stats, &mut *storage, &saiblo, &config, max_pages)?
+        }
+        Command::Rate { system, top } => cli::rate(&stats, system, top),
+        Command::Schedule { dry_run, count } => {
+            create_matches(&stats, &saiblo, &config, count, dry_run)?
+        }
+        Command::Report { out } => {
+            std::fs::create_dir_all(&out)?;
+            stats.save_reports(&out, &config.report)?;
+        }
+        Command::Query {
+            query: Query::Agent { token },
+        } => cli::query_agent(&stats, &token)?,
+        Command::Export { format, dir } => dataset::export(&stats, dir, format)?,
+        Command::Import { format, dir } => {
+            let count = dataset::import(&mut stats, &mut *storage, dir, format)?;
+            println!("Imported {count} matches");
+            stats.save(&mut *storage, &config.report)?;
+        }
     }
-    println!("Collected {} matches", stats.matches.len());
-    stats.save()?;
-    create_matches(&stats);
 
     Ok(())
 }
+
+/// Fetches up to `max_pages` pages of new or missing matches and saves them.
+fn fetch_all(
+    stats: &mut Stats,
+    storage: &mut dyn Storage,
+    saiblo: &dyn Saiblo,
+    config: &Config,
+    max_pages: usize,
+) -> Result<()> {
+    let cache = ReplayCache::new(
+        &config.replay_cache.dir,
+        config.replay_cache.compress,
+        config.replay_cache.max_bytes,
+    )?;
+
+    let gaps = fetch::fetch(
+        stats,
+        storage,
+        &cache,
+        saiblo,
+        config.saiblo.page_size,
+        max_pages,
+        config.api.download_workers,
+    )?;
+    println!(
+        "Collected {} matches and {} draws",
+        stats.matches.len(),
+        stats.draws.len()
+    );
+    if gaps > 0 {
+        println!("{gaps} gaps remain in the match history, fetch again to fill them");
+    }
+    for (id, first_seen) in stats.stuck(Utc::now(), config.saiblo.pending_timeout()) {
+        eprintln!(
+            "Match {id} has been pending since {}",
+            first_seen.with_timezone(&Local).format("%F %T")
+        );
+    }
+    stats.save(storage, &config.report)?;
+    cache.evict()
+}
@@ -0,0 +1,205 @@
+//! Upgrades stored data written by older versions, one schema version at a time.
+//!
+//! To change the storage format, bump the schema version by appending a migration to
+//! `JSON_MIGRATIONS` and/or `SQLITE_MIGRATIONS`, and add a fixture of the previous version to
+//! `tests/fixtures`.
+
+use color_eyre::eyre::{bail, OptionExt, Result};
+use rusqlite::Connection;
+use serde_json::{json, Map, Value};
+
+type JsonMigration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// `JSON_MIGRATIONS[i]` upgrades `storage.json` from version `i` to `i + 1`.
+const JSON_MIGRATIONS: &[JsonMigration] = &[json_v0_to_v1];
+
+pub const JSON_SCHEMA_VERSION: u32 = JSON_MIGRATIONS.len() as u32;
+
+/// `SQLITE_MIGRATIONS[i]` upgrades the database from `user_version` `i` to `i + 1`.
+const SQLITE_MIGRATIONS: &[&str] = &[include_str!("sql/v0_to_v1.sql")];
+
+pub const SQLITE_SCHEMA_VERSION: u32 = SQLITE_MIGRATIONS.len() as u32;
+
+/// Upgrades the content of a JSON storage in place. Files without `schema_version` are version 0.
+pub fn migrate_json(value: &mut Value) -> Result<()> {
+    let stats = value
+        .as_object_mut()
+        .ok_or_eyre("storage is not a JSON object")?;
+    let version = match stats.get("schema_version") {
+        Some(v) => v.as_u64().ok_or_eyre("invalid schema_version")? as u32,
+        None => 0,
+    };
+    if version > JSON_SCHEMA_VERSION {
+        bail!("storage schema version {version} is newer than supported {JSON_SCHEMA_VERSION}");
+    }
+    for migration in &JSON_MIGRATIONS[version as usize..] {
+        migration(stats)?;
+    }
+    stats.insert("schema_version".to_string(), JSON_SCHEMA_VERSION.into());
+    Ok(())
+}
+
+/// Creates or upgrades the tables, tracking the version in `PRAGMA user_version`.
+pub fn migrate_sqlite(conn: &mut Connection) -> Result<()> {
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SQLITE_SCHEMA_VERSION {
+        bail!("database schema version {version} is newer than supported {SQLITE_SCHEMA_VERSION}");
+    }
+    for (from, migration) in SQLITE_MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", from as u32 + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// The unversioned `storage.json` of the first releases:
+///
+/// - Failures were a set of match ids, so their kind, role and opponent are unknown.
+/// - Draws, past seasons and analytics were not kept.
+/// - Matches were all of the current logic version, and their Saiblo metadata is unknown.
+/// - Everything up to the newest collected match was assumed listed, except from the oldest
+///   awaited match on, which is listed again to find the pending matches.
+fn json_v0_to_v1(stats: &mut Map<String, Value>) -> Result<()> {
+    if let Some(agents) = stats.get_mut("agents").and_then(Value::as_object_mut) {
+        for agent in agents.values_mut() {
+            let agent = agent.as_object_mut().ok_or_eyre("agent is not an object")?;
+            let failures = match agent.remove("failure") {
+                Some(Value::Array(ids)) => ids
+                    .into_iter()
+                    .map(|id| {
+                        let id = id.as_u64().ok_or_eyre("invalid failure id")?;
+                        Ok((
+                            id.to_string(),
+                            json!({ "kind": "Unknown", "role": null, "opponent": null }),
+                        ))
+                    })
+                    .collect::<Result<Map<_, _>>>()?,
+                _ => Map::new(),
+            };
+            agent.insert("failure".to_string(), failures.into());
+        }
+    }
+
+    let logic_version = stats.get("logic_version").cloned().unwrap_or(0.into());
+    let matches = stats.get_mut("matches").and_then(Value::as_object_mut);
+    for m in matches.into_iter().flat_map(|matches| matches.values_mut()) {
+        if let Some(m) = m.as_object_mut() {
+            m.insert("logic_version".to_string(), logic_version.clone());
+        }
+    }
+
+    let newest = stats
+        .get("matches")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|matches| matches.keys())
+        .filter_map(|id| id.parse::<u32>().ok())
+        .max();
+    let awaiting = stats
+        .remove("awaiting")
+        .and_then(|v| v.as_u64())
+        .and_then(|id| u32::try_from(id).ok());
+    let listed = match awaiting {
+        Some(0) => None,
+        Some(awaiting) => newest.map(|newest| newest.min(awaiting - 1)),
+        None => newest,
+    };
+    let cursor = match listed {
+        Some(listed) => json!([[0, listed]]),
+        None => json!([]),
+    };
+    stats.insert("fetch_cursor".to_string(), cursor);
+
+    for key in ["draws", "seasons", "analytics", "pending"] {
+        stats.insert(key.to_string(), json!({}));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure::FailureKind;
+    use crate::stats::Stats;
+
+    fn load_fixture(fixture: &str) -> Stats {
+        let mut value = serde_json::from_str(fixture).unwrap();
+        migrate_json(&mut value).unwrap();
+        assert_eq!(value["schema_version"], JSON_SCHEMA_VERSION);
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn json_v0() {
+        let stats = load_fixture(include_str!("../tests/fixtures/storage-v0.json"));
+        assert_eq!(stats.logic_version, 3);
+        assert_eq!(stats.matches.len(), 2);
+        assert!(stats.draws.is_empty() && stats.seasons.is_empty());
+        assert!(stats.analytics.is_empty() && stats.pending.is_empty());
+
+        let alice = &stats.agents["token-alice"];
+        assert_eq!(alice.failure.len(), 2);
+        assert!(alice
+            .failure
+            .values()
+            .all(|f| f.kind == FailureKind::Unknown && f.role.is_none()));
+        assert!(stats.agents["token-bob"].failure.is_empty());
+
+        let m = &stats.matches[&11];
+        assert_eq!(m.logic_version, 3);
+        assert!(m.created_at.is_none() && m.creator.is_none());
+        assert_eq!(stats.fetch_cursor.ranges(), [(0, 13)]);
+    }
+
+    fn cursor_of(mut value: Value) -> Vec<(u32, u32)> {
+        migrate_json(&mut value).unwrap();
+        let stats: Stats = serde_json::from_value(value).unwrap();
+        stats.fetch_cursor.ranges().to_vec()
+    }
+
+    #[test]
+    fn json_v0_awaiting() {
+        let matches = json!({
+            "11": { "rollman": "a", "ghost": "b", "rollman_score": 1, "ghost_score": 0 },
+            "13": { "rollman": "a", "ghost": "b", "rollman_score": 1, "ghost_score": 0 },
+        });
+        let stats = |awaiting: Option<u32>| {
+            let mut stats = json!({ "agents": {}, "matches": matches, "logic_version": 1 });
+            if let Some(awaiting) = awaiting {
+                stats["awaiting"] = awaiting.into();
+            }
+            stats
+        };
+        // Listed again from the awaited match on.
+        assert_eq!(cursor_of(stats(Some(12))), [(0, 11)]);
+        assert_eq!(cursor_of(stats(Some(20))), [(0, 13)]);
+        assert!(cursor_of(stats(Some(0))).is_empty());
+        // Nothing was awaited.
+        assert_eq!(cursor_of(stats(Some(u32::MAX))), [(0, 13)]);
+        assert_eq!(cursor_of(stats(None)), [(0, 13)]);
+        assert!(cursor_of(
+            json!({ "agents": {}, "matches": {}, "logic_version": 1, "awaiting": 12 })
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn json_too_new() {
+        let mut value = json!({ "schema_version": JSON_SCHEMA_VERSION + 1 });
+        assert!(migrate_json(&mut value).is_err());
+    }
+
+    #[test]
+    fn sqlite_from_scratch() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_sqlite(&mut conn).unwrap();
+        let version: u32 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SQLITE_SCHEMA_VERSION);
+        // Migrating again is a no-op.
+        migrate_sqlite(&mut conn).unwrap();
+    }
+}
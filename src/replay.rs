@@ -0,0 +1,151 @@
+//! Typed model of RollMan replays, which are JSON Lines with one frame per line.
+//!
+//! Every field is optional and fields not modelled here are ignored, so replays of any logic
+//! version can be read.
+
+use color_eyre::eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::io::BufRead;
+
+#[derive(Deserialize)]
+pub struct Frame {
+    #[serde(default, alias = "turn", deserialize_with = "lenient")]
+    pub round: Option<u32>,
+    /// `(rollman, ghost)`
+    #[serde(default, deserialize_with = "lenient")]
+    pub score: Option<(i16, i16)>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub rollman: Option<Position>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub ghosts: Vec<Position>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub actions: Actions,
+}
+
+/// Falls back to the default if a known field has an unexpected shape in some logic version.
+fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    Ok(serde_json::from_value(Value::deserialize(deserializer)?).unwrap_or_default())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Position {
+    pub fn distance(self, other: Self) -> u32 {
+        self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Pair(i32, i32),
+            Object { x: i32, y: i32 },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Pair(x, y) | Repr::Object { x, y } => Self { x, y },
+        })
+    }
+}
+
+#[derive(Default, Deserialize)]
+pub struct Actions {
+    #[serde(default)]
+    pub rollman: Option<Action>,
+    #[serde(default, alias = "ghosts")]
+    pub ghost: Vec<Action>,
+}
+
+/// An action as sent by a bot. Numeric actions are kept as their decimal representation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Action(pub String);
+
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::String(s) => Self(s),
+            other => Self(other.to_string()),
+        })
+    }
+}
+
+/// Parses frames one line at a time without reading the whole replay into memory.
+pub struct FrameReader<R> {
+    lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for FrameReader<R> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(Into::into));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_replay() {
+        let replay = include_bytes!("../tests/fixtures/replay.jsonl");
+        let frames = FrameReader::new(&replay[..])
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(frames.len(), 4);
+
+        let first = &frames[0];
+        assert_eq!(first.round, Some(0));
+        assert_eq!(first.rollman, Some(Position { x: 1, y: 1 }));
+        assert_eq!(first.ghosts.len(), 2);
+
+        let second = &frames[1];
+        assert_eq!(second.actions.rollman, Some(Action("R".to_string())));
+        assert_eq!(second.actions.ghost[1], Action("3".to_string()));
+        assert_eq!(second.ghosts[0], Position { x: 5, y: 4 });
+
+        assert_eq!(frames[3].score, Some((7, 2)));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let frame: Frame =
+            serde_json::from_str(r#"{"score": [1, 2], "map": [[0]], "new_field": true}"#).unwrap();
+        assert_eq!(frame.score, Some((1, 2)));
+        assert_eq!(frame.round, None);
+
+        let frame: Frame =
+            serde_json::from_str(r#"{"score": [1, 2], "rollman": {"pos": 3}}"#).unwrap();
+        assert_eq!(frame.score, Some((1, 2)));
+        assert_eq!(frame.rollman, None);
+    }
+}
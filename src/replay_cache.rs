@@ -0,0 +1,239 @@
+use crate::atomic::AtomicFile;
+use color_eyre::eyre::Result;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Downloaded replays on disk, so they never need to be downloaded again.
+///
+/// Replays are stored by the SHA-256 of their content under `objects/`, and `ids/<match id>`
+/// holds the hash of the replay of each match. Reading a replay refreshes its modification time,
+/// which `evict` uses to drop the least recently used replays first.
+pub struct ReplayCache {
+    dir: PathBuf,
+    compress: bool,
+    max_bytes: u64,
+}
+
+impl ReplayCache {
+    pub fn new(dir: impl Into<PathBuf>, compress: bool, max_bytes: u64) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("objects"))?;
+        fs::create_dir_all(dir.join("ids"))?;
+        Ok(Self {
+            dir,
+            compress,
+            max_bytes,
+        })
+    }
+
+    pub fn get(&self, id: u32) -> Result<Option<Vec<u8>>> {
+        let Ok(hash) = fs::read_to_string(self.id_path(id)) else {
+            return Ok(None);
+        };
+        let hash = hash.trim();
+        if !is_hash(hash) {
+            // Truncated by a crash or edited by hand.
+            let _ = fs::remove_file(self.id_path(id));
+            return Ok(None);
+        }
+        for compressed in [true, false] {
+            let path = self.object_path(hash, compressed);
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let replay = if compressed {
+                zstd::decode_all(data.as_slice()).ok()
+            } else {
+                Some(data)
+            };
+            let Some(replay) = replay else {
+                // Removed so that `insert` writes it again.
+                let _ = fs::remove_file(&path);
+                break;
+            };
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(SystemTime::now())?;
+            return Ok(Some(replay));
+        }
+        // The replay has been evicted or was corrupt.
+        let _ = fs::remove_file(self.id_path(id));
+        Ok(None)
+    }
+
+    pub fn insert(&self, id: u32, replay: &[u8]) -> Result<()> {
+        let hash = Sha256::digest(replay)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+
+        let path = self.object_path(&hash, self.compress);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            let mut object = AtomicFile::create(&path)?;
+            if self.compress {
+                object.write_all(&zstd::encode_all(replay, 0)?)?;
+            } else {
+                object.write_all(replay)?;
+            }
+            object.commit()?;
+        }
+
+        let mut link = AtomicFile::create(self.id_path(id))?;
+        write!(link, "{hash}")?;
+        link.commit()
+    }
+
+    /// Removes the least recently used replays until the cache fits in `max_bytes`, then the ids
+    /// that pointed to them.
+    pub fn evict(&self) -> Result<()> {
+        let mut objects = Vec::new();
+        let mut total = 0;
+        for shard in fs::read_dir(self.dir.join("objects"))? {
+            for object in fs::read_dir(shard?.path())? {
+                let object = object?;
+                let metadata = object.metadata()?;
+                total += metadata.len();
+                objects.push((metadata.modified()?, metadata.len(), object.path()));
+            }
+        }
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        objects.sort_unstable();
+        for (_, len, path) in objects {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(path)?;
+            total -= len;
+        }
+
+        for link in fs::read_dir(self.dir.join("ids"))? {
+            let path = link?.path();
+            let hash = fs::read_to_string(&path).unwrap_or_default();
+            let hash = hash.trim();
+            let evicted = !is_hash(hash)
+                || [true, false]
+                    .into_iter()
+                    .all(|compressed| !self.object_path(hash, compressed).exists());
+            if evicted {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn id_path(&self, id: u32) -> PathBuf {
+        self.dir.join("ids").join(id.to_string())
+    }
+
+    fn object_path(&self, hash: &str, compressed: bool) -> PathBuf {
+        let name = if compressed {
+            format!("{hash}.jsonl.zst")
+        } else {
+            format!("{hash}.jsonl")
+        };
+        self.dir.join("objects").join(&hash[..2]).join(name)
+    }
+}
+
+/// Whether `hash` is a hex SHA-256, as written by `insert`.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cache(name: &str, compress: bool, max_bytes: u64) -> ReplayCache {
+        let dir =
+            std::env::temp_dir().join(format!("rollman-elo-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ReplayCache::new(dir, compress, max_bytes).unwrap()
+    }
+
+    fn objects(cache: &ReplayCache) -> Vec<PathBuf> {
+        fs::read_dir(cache.dir.join("objects"))
+            .unwrap()
+            .flat_map(|shard| fs::read_dir(shard.unwrap().path()).unwrap())
+            .map(|object| object.unwrap().path())
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = cache("evict", false, 200);
+        let long_ago = SystemTime::now() - Duration::from_secs(3600);
+        for id in 1..=3 {
+            cache.insert(id, &[id as u8; 100]).unwrap();
+            let hash = fs::read_to_string(cache.id_path(id)).unwrap();
+            File::options()
+                .write(true)
+                .open(cache.object_path(&hash, false))
+                .unwrap()
+                .set_modified(long_ago + Duration::from_secs(id.into()))
+                .unwrap();
+        }
+        // Reading 1 makes 2 the least recently used.
+        assert!(cache.get(1).unwrap().is_some());
+
+        cache.evict().unwrap();
+        assert_eq!(objects(&cache).len(), 2);
+        assert_eq!(cache.get(1).unwrap(), Some(vec![1; 100]));
+        assert!(!cache.id_path(2).exists());
+        assert_eq!(cache.get(2).unwrap(), None);
+        assert_eq!(cache.get(3).unwrap(), Some(vec![3; 100]));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let cache = cache("zstd", true, u64::MAX);
+        let replay = include_bytes!("../tests/fixtures/replay.jsonl");
+        cache.insert(1, replay).unwrap();
+
+        let [object] = objects(&cache).try_into().unwrap();
+        assert!(object.to_str().unwrap().ends_with(".jsonl.zst"));
+        assert!(fs::metadata(object).unwrap().len() < replay.len() as u64);
+        assert_eq!(cache.get(1).unwrap().as_deref(), Some(&replay[..]));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_object() {
+        let cache = cache("corrupt", true, u64::MAX);
+        cache.insert(1, b"replay").unwrap();
+        let [object] = objects(&cache).try_into().unwrap();
+        fs::write(&object, b"not zstd").unwrap();
+
+        assert_eq!(cache.get(1).unwrap(), None);
+        assert!(!cache.id_path(1).exists());
+        cache.insert(1, b"replay").unwrap();
+        assert_eq!(cache.get(1).unwrap().as_deref(), Some(&b"replay"[..]));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn id_index() {
+        let cache = cache("ids", false, u64::MAX);
+        cache.insert(1, b"same").unwrap();
+        cache.insert(2, b"same").unwrap();
+        assert_eq!(objects(&cache).len(), 1);
+        assert_eq!(cache.get(2).unwrap().as_deref(), Some(&b"same"[..]));
+        assert_eq!(cache.get(3).unwrap(), None);
+
+        // A truncated hash is a miss.
+        fs::write(cache.id_path(1), "a").unwrap();
+        assert_eq!(cache.get(1).unwrap(), None);
+        assert!(!cache.id_path(1).exists());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
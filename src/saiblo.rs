@@ -0,0 +1,272 @@
+//! The Saiblo endpoints used by this crate, behind the `Saiblo` trait so tests can use a fake.
+
+use crate::api::{Api, Endpoint};
+use crate::config::Config;
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const JUDGING: &str = "评测中";
+pub const WAITING: &str = "准备中";
+
+#[derive(Deserialize)]
+pub struct MatchList {
+    /// The number of matches of the game in total.
+    pub count: usize,
+    pub results: Vec<MatchInfo>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MatchInfo {
+    pub id: u32,
+    pub logic_version: Option<u16>,
+    pub state: String,
+    pub info: (AgentInfo, AgentInfo),
+    #[serde(default)]
+    pub create_time: Option<String>,
+    #[serde(default)]
+    pub finish_time: Option<String>,
+    /// Either the id or an object with `id`.
+    #[serde(default)]
+    pub room: Option<Value>,
+    /// Either the username or an object with `username`.
+    #[serde(default)]
+    pub creator: Option<Value>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AgentInfo {
+    pub code: Option<CodeInfo>,
+    pub user: UserInfo,
+    pub score: i16,
+    pub end_state: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CodeInfo {
+    pub id: String,
+    pub entity: String,
+    pub version: u32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+}
+
+#[derive(Serialize)]
+struct GameInfo {
+    game_id: u32,
+    player_number: u32,
+}
+
+#[derive(Deserialize)]
+struct Room {
+    id: u32,
+}
+
+#[derive(Serialize)]
+struct Join<'a> {
+    enter: bool,
+    entity: &'a str,
+    is_remote: bool,
+    is_user: bool,
+    order: u8,
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: usize,
+}
+
+/// `Sync` so replays can be downloaded concurrently.
+pub trait Saiblo: Sync {
+    /// Up to `limit` matches of the game, newest first, skipping the newest `offset` ones.
+    fn list_matches(&self, offset: usize, limit: usize) -> Result<MatchList>;
+
+    fn get_match(&self, id: u32) -> Result<MatchInfo>;
+
+    fn download_replay(&self, id: u32) -> Result<Vec<u8>>;
+
+    /// Returns the id of the new room.
+    fn create_room(&self) -> Result<u32>;
+
+    /// Adds the agent `entity` to the room as player `order`.
+    fn join_room(&self, room: u32, entity: &str, order: u8) -> Result<()>;
+
+    fn begin_match(&self, room: u32) -> Result<()>;
+
+    /// The number of matches of the game in `state`, e.g. `JUDGING`.
+    fn count_matches_by_state(&self, state: &str) -> Result<usize>;
+}
+
+pub struct SaibloClient<'a> {
+    api: Api<'a>,
+}
+
+impl<'a> SaibloClient<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            api: Api::new(config),
+        }
+    }
+
+    fn post(&self, path: &str, json: impl Serialize) -> Result<Vec<u8>> {
+        self.api.post(path, Some(&serde_json::to_string(&json)?))
+    }
+}
+
+impl Saiblo for SaibloClient<'_> {
+    fn list_matches(&self, offset: usize, limit: usize) -> Result<MatchList> {
+        let query = [
+            ("limit", limit.to_string()),
+            ("offset", offset.to_string()),
+            ("game", self.api.saiblo.game_id.to_string()),
+        ];
+        self.api.get_json(Endpoint::List, "matches/", &query)
+    }
+
+    fn get_match(&self, id: u32) -> Result<MatchInfo> {
+        self.api
+            .get_json(Endpoint::List, &format!("matches/{id}/"), &[])
+    }
+
+    fn download_replay(&self, id: u32) -> Result<Vec<u8>> {
+        self.api.download(&format!("matches/{id}/download/"))
+    }
+
+    fn create_room(&self) -> Result<u32> {
+        let game_info = GameInfo {
+            game_id: self.api.saiblo.game_id,
+            player_number: 2,
+        };
+        let room: Room = serde_json::from_slice(&self.post("rooms/", game_info)?)?;
+        Ok(room.id)
+    }
+
+    fn join_room(&self, room: u32, entity: &str, order: u8) -> Result<()> {
+        let join = Join {
+            enter: true,
+            entity,
+            is_remote: false,
+            is_user: false,
+            order,
+        };
+        self.post(&format!("rooms/{room}/join/"), join)?;
+        Ok(())
+    }
+
+    fn begin_match(&self, room: u32) -> Result<()> {
+        self.api.post(&format!("rooms/{room}/begin_match/"), None)?;
+        Ok(())
+    }
+
+    fn count_matches_by_state(&self, state: &str) -> Result<usize> {
+        let query = [
+            ("limit", "1".to_string()),
+            ("state", state.to_string()),
+            ("game", self.api.saiblo.game_id.to_string()),
+        ];
+        let count: Count = self.api.get_json(Endpoint::List, "matches/", &query)?;
+        Ok(count.count)
+    }
+}
+
+/// An in-memory Saiblo for tests.
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use color_eyre::eyre::OptionExt;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub struct FakeSaiblo {
+        /// Newest first, like Saiblo lists them.
+        pub matches: Vec<MatchInfo>,
+        pub replays: HashMap<u32, Vec<u8>>,
+        /// The players of each created room, by order.
+        pub rooms: Mutex<Vec<Vec<String>>>,
+        /// The rooms whose match has begun.
+        pub begun: Mutex<Vec<u32>>,
+    }
+
+    impl FakeSaiblo {
+        /// Adds a finished match between two agents, with the first one as the rollman.
+        pub fn add_match(
+            &mut self,
+            id: u32,
+            (rollman, rollman_score): (&str, i16),
+            (ghost, ghost_score): (&str, i16),
+            replay: &[u8],
+        ) {
+            let agent = |token: &str, score| AgentInfo {
+                code: Some(CodeInfo {
+                    id: token.to_string(),
+                    entity: format!("{token}-entity"),
+                    version: 1,
+                }),
+                user: UserInfo {
+                    username: format!("{token}-user"),
+                },
+                score,
+                end_state: Some("OK".to_string()),
+            };
+            let info = MatchInfo {
+                id,
+                logic_version: Some(0),
+                state: "评测成功".to_string(),
+                info: (agent(rollman, rollman_score), agent(ghost, ghost_score)),
+                create_time: None,
+                finish_time: None,
+                room: None,
+                creator: None,
+            };
+            let index = self.matches.partition_point(|m| m.id > id);
+            self.matches.insert(index, info);
+            self.replays.insert(id, replay.to_vec());
+        }
+    }
+
+    impl Saiblo for FakeSaiblo {
+        fn list_matches(&self, offset: usize, limit: usize) -> Result<MatchList> {
+            let results = self.matches.iter().skip(offset).take(limit).cloned();
+            Ok(MatchList {
+                count: self.matches.len(),
+                results: results.collect(),
+            })
+        }
+
+        fn get_match(&self, id: u32) -> Result<MatchInfo> {
+            let m = self.matches.iter().find(|m| m.id == id);
+            m.cloned().ok_or_eyre("404 Not Found")
+        }
+
+        fn download_replay(&self, id: u32) -> Result<Vec<u8>> {
+            self.replays.get(&id).cloned().ok_or_eyre("404 Not Found")
+        }
+
+        fn create_room(&self) -> Result<u32> {
+            let mut rooms = self.rooms.lock().unwrap();
+            rooms.push(Vec::new());
+            Ok(rooms.len() as u32 - 1)
+        }
+
+        fn join_room(&self, room: u32, entity: &str, order: u8) -> Result<()> {
+            let mut rooms = self.rooms.lock().unwrap();
+            let players = rooms.get_mut(room as usize).ok_or_eyre("no such room")?;
+            assert_eq!(players.len(), order as usize);
+            players.push(entity.to_string());
+            Ok(())
+        }
+
+        fn begin_match(&self, room: u32) -> Result<()> {
+            self.begun.lock().unwrap().push(room);
+            Ok(())
+        }
+
+        fn count_matches_by_state(&self, state: &str) -> Result<usize> {
+            Ok(self.matches.iter().filter(|m| m.state == state).count())
+        }
+    }
+}
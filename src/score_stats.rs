@@ -0,0 +1,141 @@
+use crate::stats::*;
+
+/// How many of the most recent matches the trend is computed over.
+pub const TREND_WINDOW: usize = 20;
+
+pub struct ScoreStats {
+    pub count: usize,
+    pub mean: f32,
+    pub std_dev: f32,
+    pub min: i16,
+    pub max: i16,
+    /// The 25th, 50th and 75th percentiles.
+    pub quantiles: [f32; 3],
+    /// Least-squares slope of the score per match over the last `TREND_WINDOW` matches.
+    pub trend: f32,
+}
+
+impl ScoreStats {
+    /// `scores` must be ordered by match id.
+    pub fn new(scores: &[i16]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+
+        let count = scores.len();
+        let mean = scores.iter().map(|&s| s as f32).sum::<f32>() / count as f32;
+        let variance = scores
+            .iter()
+            .map(|&s| (s as f32 - mean).powi(2))
+            .sum::<f32>()
+            / count as f32;
+
+        let mut sorted = scores.to_vec();
+        sorted.sort_unstable();
+        let quantile = |q: f32| {
+            let pos = q * (count - 1) as f32;
+            let lo = pos.floor() as usize;
+            let hi = pos.ceil() as usize;
+            let (lo_score, hi_score) = (sorted[lo] as i32, sorted[hi] as i32);
+            lo_score as f32 + (hi_score - lo_score) as f32 * (pos - lo as f32)
+        };
+
+        let recent = &scores[count.saturating_sub(TREND_WINDOW)..];
+        let n = recent.len() as f32;
+        let x_mean = (n - 1.0) / 2.0;
+        let y_mean = recent.iter().map(|&s| s as f32).sum::<f32>() / n;
+        let (cov, var) = recent
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(cov, var), (x, &y)| {
+                let dx = x as f32 - x_mean;
+                (cov + dx * (y as f32 - y_mean), var + dx * dx)
+            });
+        let trend = if var == 0.0 { 0.0 } else { cov / var };
+
+        Some(Self {
+            count,
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[count - 1],
+            quantiles: [quantile(0.25), quantile(0.5), quantile(0.75)],
+            trend,
+        })
+    }
+}
+
+impl Stats {
+    pub fn rollman_score_stats(&self, token: &str) -> Option<ScoreStats> {
+        let mut scores = self
+            .matches_with_rollman
+            .get(token)?
+            .iter()
+            .map(|(id, m)| (*id, m.rollman_score))
+            .collect::<Vec<_>>();
+        scores.sort_unstable();
+        ScoreStats::new(&scores.into_iter().map(|(_, s)| s).collect::<Vec<_>>())
+    }
+
+    pub fn ghost_score_stats(&self, token: &str) -> Option<ScoreStats> {
+        let mut scores = self
+            .matches_with_ghost
+            .get(token)?
+            .iter()
+            .map(|(id, m)| (*id, m.ghost_score))
+            .collect::<Vec<_>>();
+        scores.sort_unstable();
+        ScoreStats::new(&scores.into_iter().map(|(_, s)| s).collect::<Vec<_>>())
+    }
+}
+
+/// The "Score" and "Trend" cells of a ranking row.
+pub fn score_cells(stats: Option<&ScoreStats>) -> String {
+    match stats {
+        Some(s) => format!(
+            r#"<td title="min {} / p25 {:.1} / p50 {:.1} / p75 {:.1} / max {}">{:.1} ± {:.1}</td><td>{:+.2}</td>"#,
+            s.min,
+            s.quantiles[0],
+            s.quantiles[1],
+            s.quantiles[2],
+            s.max,
+            s.mean,
+            s.std_dev,
+            s.trend,
+        ),
+        None => "<td></td><td></td>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let stats = ScoreStats::new(&[5, 2, 4, 9, 4, 7, 4, 5]).unwrap();
+        assert_eq!(stats.count, 8);
+        assert_eq!((stats.min, stats.max), (2, 9));
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.std_dev, 2.0);
+        assert_eq!(stats.quantiles, [4.0, 4.5, 5.5]);
+        assert!(ScoreStats::new(&[]).is_none());
+    }
+
+    #[test]
+    fn extreme_scores() {
+        let stats = ScoreStats::new(&[i16::MIN, i16::MAX]).unwrap();
+        assert_eq!(stats.quantiles[1], -0.5);
+        assert_eq!(stats.mean, -0.5);
+    }
+
+    #[test]
+    fn trend() {
+        assert_eq!(ScoreStats::new(&[1, 3, 5, 7]).unwrap().trend, 2.0);
+        assert_eq!(ScoreStats::new(&[4]).unwrap().trend, 0.0);
+        // Only the last `TREND_WINDOW` matches count.
+        let mut scores = vec![100; 10];
+        scores.extend((0..TREND_WINDOW as i16).map(|i| -i));
+        assert_eq!(ScoreStats::new(&scores).unwrap().trend, -1.0);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::atomic::AtomicFile;
+use crate::stats::*;
+use chrono::Local;
+use color_eyre::eyre::Result;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+/// Matches and final ratings of a past logic version.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Season {
+    pub agents: HashMap<String, SeasonAgent>,
+    pub matches: BTreeMap<u32, Match>,
+    pub draws: BTreeMap<u32, Draw>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SeasonAgent {
+    pub user: String,
+    pub name: String,
+    pub version: u32,
+    pub rollman_elo: f32,
+    pub ghost_elo: f32,
+    pub rollman_count: usize,
+    pub ghost_count: usize,
+    pub failure_count: usize,
+}
+
+impl SeasonAgent {
+    pub fn can_rollman(&self) -> bool {
+        self.rollman_count > self.failure_count.saturating_sub(50) * 10
+    }
+
+    pub fn can_ghost(&self) -> bool {
+        self.ghost_count > self.failure_count.saturating_sub(50) * 10
+    }
+}
+
+const SEASON_TOP: usize = 20;
+
+impl Stats {
+    /// Moves the current matches and ratings into `seasons` and starts over with `logic_version`.
+    pub fn archive(&mut self, logic_version: u16) {
+        if !self.matches.is_empty() || !self.draws.is_empty() {
+            let agents = self
+                .agents
+                .iter()
+                .filter(|(_, a)| a.rollman_count + a.ghost_count + a.failure.len() > 0)
+                .map(|(token, a)| {
+                    let agent = SeasonAgent {
+                        user: a.user.clone(),
+                        name: a.name.clone(),
+                        version: a.version,
+                        rollman_elo: a.rollman_elo,
+                        ghost_elo: a.ghost_elo,
+                        rollman_count: a.rollman_count,
+                        ghost_count: a.ghost_count,
+                        failure_count: a.failure.len(),
+                    };
+                    (token.clone(), agent)
+                })
+                .collect();
+            let season = Season {
+                agents,
+                matches: std::mem::take(&mut self.matches),
+                draws: std::mem::take(&mut self.draws),
+            };
+            self.seasons.insert(self.logic_version, season);
+        }
+
+        let seasons = std::mem::take(&mut self.seasons);
+        let analytics = std::mem::take(&mut self.analytics);
+        let rating = std::mem::take(&mut self.rating);
+        *self = Self::default();
+        self.seasons = seasons;
+        self.analytics = analytics;
+        self.rating = rating;
+        self.logic_version = logic_version;
+    }
+
+    pub fn save_seasons(&self, out: &Path) -> Result<()> {
+        let mut buf = AtomicFile::create(out.join("seasons.html"))?;
+
+        write!(
+            buf,
+            r#"<!DOCTYPE html>
+<html>
+
+<head>
+  <title>RollMan Seasons</title>
+  <style>
+    table {{ border-collapse: collapse; margin-top: 1rem; }}
+    th, td {{ padding: 10px; border: 1px solid #ddd; }}
+    th {{ background-color: #f5f5f5; }}
+    .flex {{ display: flex; flex-wrap: wrap; justify-content: space-around; }}
+    section {{ margin: 2rem; }}
+  </style>
+  <script defer data-domain="misc.ouuan.moe" src="https://plausible.ouuan.moe/js/script.js"></script>
+</head>
+
+<body>
+  <div>
+    <a href="ranking.html">Ranking</a>
+    最后更新于 {}
+  </div>"#,
+            Local::now().format("%F %T"),
+        )?;
+
+        for (logic_version, season) in self.seasons.iter().rev() {
+            write!(
+                buf,
+                r#"
+  <section>
+    <h2>Logic version {}</h2>
+    <div>{} matches, {} draws</div>
+    <div class="flex">"#,
+                logic_version,
+                season.matches.len(),
+                season.draws.len(),
+            )?;
+
+            let mut rollmen: Vec<_> = season.agents.values().filter(|a| a.can_rollman()).collect();
+            rollmen.sort_by_key(|a| Reverse(OrderedFloat(a.rollman_elo)));
+            let mut ghosts: Vec<_> = season.agents.values().filter(|a| a.can_ghost()).collect();
+            ghosts.sort_by_key(|a| Reverse(OrderedFloat(a.ghost_elo)));
+
+            for (title, agents, rollman) in [("Rollman", rollmen, true), ("Ghost", ghosts, false)] {
+                write!(
+                    buf,
+                    r#"
+      <table>
+        <tr><th colspan="5">{title}</th></tr>
+        <tr><th>#</th><th>User</th><th>Bot</th><th>Ver.</th><th>Elo</th></tr>"#
+                )?;
+                for (rank, agent) in agents.into_iter().take(SEASON_TOP).enumerate() {
+                    let elo = if rollman {
+                        agent.rollman_elo
+                    } else {
+                        agent.ghost_elo
+                    };
+                    write!(
+                        buf,
+                        r#"
+        <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td {}>{:.0}</td></tr>"#,
+                        rank + 1,
+                        escape_html(&agent.user),
+                        escape_html(&agent.name),
+                        agent.version,
+                        rating_color(elo),
+                        elo,
+                    )?;
+                }
+                write!(
+                    buf,
+                    "
+      </table>"
+                )?;
+            }
+
+            let carried = self.carried_over(*logic_version);
+            write!(
+                buf,
+                r#"
+    </div>
+    <h3>Carried over to the next version ({})</h3>
+    <table>
+      <tr>
+        <th>User</th><th>Bot</th><th>Ver.</th>
+        <th>Rollman Elo</th><th>Next</th>
+        <th>Ghost Elo</th><th>Next</th>
+      </tr>"#,
+                carried.len(),
+            )?;
+            for (agent, (next_rollman, next_ghost)) in carried {
+                write!(
+                    buf,
+                    r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td>
+        <td {}>{:.0}</td><td {}>{:.0}</td>
+        <td {}>{:.0}</td><td {}>{:.0}</td>
+      </tr>"#,
+                    escape_html(&agent.user),
+                    escape_html(&agent.name),
+                    agent.version,
+                    rating_color(agent.rollman_elo),
+                    agent.rollman_elo,
+                    rating_color(next_rollman),
+                    next_rollman,
+                    rating_color(agent.ghost_elo),
+                    agent.ghost_elo,
+                    rating_color(next_ghost),
+                    next_ghost,
+                )?;
+            }
+            write!(
+                buf,
+                "
+    </table>
+  </section>"
+            )?;
+        }
+
+        writeln!(
+            buf,
+            r#"
+</body>
+
+</html>"#
+        )?;
+        buf.commit()?;
+
+        Ok(())
+    }
+
+    /// Agents of the season that also played in the following one (archived or current), with
+    /// their `(rollman_elo, ghost_elo)` there.
+    fn carried_over(&self, logic_version: u16) -> Vec<(&SeasonAgent, (f32, f32))> {
+        let Some(season) = self.seasons.get(&logic_version) else {
+            return Vec::new();
+        };
+        let next = self
+            .seasons
+            .range(logic_version + 1..)
+            .next()
+            .map(|(_, next)| {
+                next.agents
+                    .iter()
+                    .map(|(token, a)| (token.as_str(), (a.rollman_elo, a.ghost_elo)))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_else(|| {
+                self.agents
+                    .iter()
+                    .filter(|(_, a)| a.rollman_count + a.ghost_count > 0)
+                    .map(|(token, a)| (token.as_str(), (a.rollman_elo, a.ghost_elo)))
+                    .collect()
+            });
+        let mut carried = season
+            .agents
+            .iter()
+            .filter_map(|(token, a)| Some((a, *next.get(token.as_str())?)))
+            .collect::<Vec<_>>();
+        carried.sort_by_key(|(a, _)| Reverse(OrderedFloat(a.rollman_elo.max(a.ghost_elo))));
+        carried
+    }
+}
@@ -0,0 +1,115 @@
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+CREATE TABLE agents (
+    token TEXT PRIMARY KEY,
+    user TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL
+);
+CREATE TABLE matches (
+    id INTEGER PRIMARY KEY,
+    rollman TEXT NOT NULL REFERENCES agents(token),
+    ghost TEXT NOT NULL REFERENCES agents(token),
+    rollman_score INTEGER NOT NULL,
+    ghost_score INTEGER NOT NULL,
+    logic_version INTEGER NOT NULL,
+    created_at TEXT,
+    finished_at TEXT,
+    room_id INTEGER,
+    creator TEXT
+);
+CREATE TABLE draws (
+    id INTEGER PRIMARY KEY,
+    rollman TEXT NOT NULL REFERENCES agents(token),
+    ghost TEXT NOT NULL REFERENCES agents(token),
+    rollman_score INTEGER NOT NULL,
+    ghost_score INTEGER NOT NULL,
+    -- NULL for ties
+    rollman_failure TEXT,
+    ghost_failure TEXT,
+    double_failure INTEGER NOT NULL,
+    roles_known INTEGER NOT NULL
+);
+CREATE TABLE failures (
+    match_id INTEGER NOT NULL,
+    token TEXT NOT NULL REFERENCES agents(token),
+    -- NULL if unknown
+    kind TEXT,
+    role TEXT,
+    opponent TEXT,
+    PRIMARY KEY (match_id, token)
+);
+-- Kept across logic versions, so not referencing the agents of the current one.
+CREATE TABLE rating_snapshots (
+    time TEXT NOT NULL,
+    token TEXT NOT NULL,
+    rollman_elo REAL NOT NULL,
+    ghost_elo REAL NOT NULL,
+    rollman_count INTEGER NOT NULL,
+    ghost_count INTEGER NOT NULL,
+    logic_version INTEGER NOT NULL,
+    PRIMARY KEY (time, token)
+);
+CREATE VIEW latest_ratings AS
+    SELECT agents.*, rollman_elo, ghost_elo, rollman_count, ghost_count
+    FROM rating_snapshots JOIN agents USING (token)
+    WHERE time = (SELECT MAX(time) FROM rating_snapshots);
+CREATE TABLE season_agents (
+    logic_version INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    user TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    rollman_elo REAL NOT NULL,
+    ghost_elo REAL NOT NULL,
+    rollman_count INTEGER NOT NULL,
+    ghost_count INTEGER NOT NULL,
+    failure_count INTEGER NOT NULL,
+    PRIMARY KEY (logic_version, token)
+);
+CREATE TABLE season_matches (
+    id INTEGER PRIMARY KEY,
+    logic_version INTEGER NOT NULL,
+    rollman TEXT NOT NULL,
+    ghost TEXT NOT NULL,
+    rollman_score INTEGER NOT NULL,
+    ghost_score INTEGER NOT NULL,
+    created_at TEXT,
+    finished_at TEXT,
+    room_id INTEGER,
+    creator TEXT
+);
+CREATE TABLE season_draws (
+    id INTEGER PRIMARY KEY,
+    logic_version INTEGER NOT NULL,
+    rollman TEXT NOT NULL,
+    ghost TEXT NOT NULL,
+    rollman_score INTEGER NOT NULL,
+    ghost_score INTEGER NOT NULL,
+    rollman_failure TEXT,
+    ghost_failure TEXT,
+    double_failure INTEGER NOT NULL,
+    roles_known INTEGER NOT NULL
+);
+CREATE TABLE match_analytics (
+    id INTEGER PRIMARY KEY,
+    length INTEGER NOT NULL,
+    lead_changes INTEGER NOT NULL,
+    final_score_at INTEGER NOT NULL,
+    rollman_distance INTEGER NOT NULL,
+    ghost_distance INTEGER NOT NULL,
+    -- JSON
+    score_timeline TEXT NOT NULL,
+    rollman_actions TEXT NOT NULL,
+    ghost_actions TEXT NOT NULL
+);
+CREATE TABLE fetch_cursor (
+    first_id INTEGER PRIMARY KEY,
+    last_id INTEGER NOT NULL
+);
+CREATE TABLE pending_matches (
+    id INTEGER PRIMARY KEY,
+    first_seen TEXT NOT NULL
+);
@@ -1,39 +1,109 @@
-use crate::constants::*;
+use crate::analytics::MatchAnalytics;
+use crate::atomic::AtomicFile;
+use crate::config::{RatingConfig, ReportConfig};
+use crate::cursor::FetchCursor;
 use crate::elo::elo;
-use chrono::Local;
+use crate::failure::*;
+use crate::score_stats::*;
+use crate::season::Season;
+use crate::storage::Storage;
+use chrono::{DateTime, Local, TimeDelta, Utc};
 use color_eyre::eyre::Result;
 use ordered_float::OrderedFloat;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct Stats {
     pub agents: HashMap<String, Agent>,
     pub matches: BTreeMap<u32, Match>,
+    pub draws: BTreeMap<u32, Draw>,
     pub logic_version: u16,
-    pub awaiting: u32,
+    /// Matches that are still being judged, or whose replay could not be downloaded yet, with
+    /// when they were first seen.
+    pub pending: BTreeMap<u32, DateTime<Utc>>,
+    pub fetch_cursor: FetchCursor,
+    pub seasons: BTreeMap<u16, Season>,
+    /// Analytics of every match whose replay has been parsed, including archived ones.
+    pub analytics: BTreeMap<u32, MatchAnalytics>,
     #[serde(skip)]
     pub matches_with_rollman: HashMap<String, Vec<(u32, Match)>>,
     #[serde(skip)]
     pub matches_with_ghost: HashMap<String, Vec<(u32, Match)>>,
     #[serde(skip)]
     pub count_rollman_ghost: HashMap<String, HashMap<String, u32>>,
+    #[serde(skip)]
+    pub rating: RatingConfig,
 }
 
 impl Stats {
+    /// Adds the agent unless it is already known.
+    pub fn add_agent(&mut self, token: &str, user: String, name: String, version: u32) {
+        if !self.agents.contains_key(token) {
+            let mut agent = Agent::new(user, name, version);
+            agent.rollman_elo = self.rating.elo_base;
+            agent.ghost_elo = self.rating.elo_base;
+            self.agents.insert(token.to_string(), agent);
+        }
+    }
+
     pub fn add_match(&mut self, id: u32, m: Match) {
+        self.rate_match(id, m.clone());
+        self.matches.insert(id, m);
+    }
+
+    pub fn add_draw(&mut self, id: u32, draw: Draw) {
+        self.draws.insert(id, draw);
+    }
+
+    /// Returns whether the failure is new and its agent is known.
+    pub fn add_failure(&mut self, id: u32, token: &str, failure: Failure) -> bool {
+        match self.agents.get(token) {
+            Some(agent) if !agent.failure.contains_key(&id) => {}
+            _ => return false,
+        }
+        self.rate_failure(token, &failure);
+        self.agents
+            .get_mut(token)
+            .unwrap()
+            .failure
+            .insert(id, failure);
+        true
+    }
+
+    /// The pending matches first seen more than `timeout` before `now`, oldest first.
+    pub fn stuck(&self, now: DateTime<Utc>, timeout: TimeDelta) -> Vec<(u32, DateTime<Utc>)> {
+        self.pending
+            .iter()
+            .filter(|(_, &first_seen)| now - first_seen > timeout)
+            .map(|(&id, &first_seen)| (id, first_seen))
+            .collect()
+    }
+
+    /// Whether the match has already been recorded, either decisive or not.
+    pub fn contains(&self, id: u32) -> bool {
+        self.matches.contains_key(&id) || self.draws.contains_key(&id)
+    }
+
+    fn rate_match(&mut self, id: u32, m: Match) {
         let rollman = self.agents.get_mut(&m.rollman).unwrap();
         rollman.rollman_count += 1;
-        rollman.rollman_time = rollman.rollman_time.min(id);
+        if id < rollman.rollman_time {
+            rollman.rollman_time = id;
+            rollman.rollman_since = m.created_at;
+        }
         let rollman_elo = rollman.rollman_elo;
 
         let ghost = self.agents.get_mut(&m.ghost).unwrap();
         ghost.ghost_count += 1;
-        ghost.ghost_time = ghost.ghost_time.min(id);
+        if id < ghost.ghost_time {
+            ghost.ghost_time = id;
+            ghost.ghost_since = m.created_at;
+        }
         let ghost_elo = ghost.ghost_elo;
 
         let rollman_matches = self
@@ -52,7 +122,7 @@ impl Stats {
             };
             let a = self.agents.get(&m.ghost).unwrap().ghost_elo;
             let b = self.agents.get(&n.ghost).unwrap().ghost_elo;
-            let (new_a, new_b) = elo(a, b, win, rollman_elo, rollman_matches.len());
+            let (new_a, new_b) = elo(a, b, win, rollman_elo, rollman_matches.len(), &self.rating);
             self.agents.get_mut(&m.ghost).unwrap().ghost_elo = new_a;
             self.agents.get_mut(&n.ghost).unwrap().ghost_elo = new_b;
         }
@@ -73,7 +143,7 @@ impl Stats {
             };
             let a = self.agents.get(&m.rollman).unwrap().rollman_elo;
             let b = self.agents.get(&n.rollman).unwrap().rollman_elo;
-            let (new_a, new_b) = elo(a, b, win, ghost_elo, ghost_matches.len());
+            let (new_a, new_b) = elo(a, b, win, ghost_elo, ghost_matches.len(), &self.rating);
             self.agents.get_mut(&m.rollman).unwrap().rollman_elo = new_a;
             self.agents.get_mut(&n.rollman).unwrap().rollman_elo = new_b;
         }
@@ -92,42 +162,114 @@ impl Stats {
             .entry(m.ghost.clone())
             .and_modify(|e| *e += 1)
             .or_insert(1);
-        self.matches.insert(id, m);
     }
 
-    pub fn clear(&mut self) {
-        *self = Self::default();
+    fn rate(&mut self, record: Rated) {
+        match record {
+            Rated::Match(id, m) => self.rate_match(id, m),
+            Rated::Failure(token, failure) => self.rate_failure(&token, &failure),
+        }
     }
 
-    pub fn load() -> Result<Self> {
-        let storage = match File::open("storage.json") {
-            Ok(f) => f,
-            Err(_) => return Ok(Self::default()),
+    /// Failures whose role the replay did not tell are not rated, as the penalty would hit either
+    /// role.
+    fn rate_failure(&mut self, token: &str, failure: &Failure) {
+        let policy = self.rating.failure_policy;
+        if policy == FailurePolicy::Ignore {
+            return;
+        }
+        let (Some(role), Some(opponent)) = (failure.role, &failure.opponent) else {
+            return;
+        };
+        let Some(opponent_agent) = self.agents.get(opponent) else {
+            return;
         };
-        let buf = BufReader::new(storage);
-        let mut stats: Self = serde_json::from_reader(buf)?;
+
+        match role {
+            Role::Rollman => {
+                let ghost_elo = opponent_agent.ghost_elo;
+                let ghost_matches = self
+                    .matches_with_ghost
+                    .get(opponent)
+                    .map_or::<&[_], _>(&[], Vec::as_slice);
+                let worst = ghost_matches.iter().map(|(_, n)| n.rollman_score).min();
+
+                for (_, n) in ghost_matches {
+                    if n.rollman == token {
+                        continue;
+                    }
+                    let win = match policy {
+                        FailurePolicy::WorstScore if Some(n.rollman_score) == worst => 0.5,
+                        _ => 0.0,
+                    };
+                    let a = self.agents.get(token).unwrap().rollman_elo;
+                    let b = self.agents.get(&n.rollman).unwrap().rollman_elo;
+                    let (new_a, new_b) =
+                        elo(a, b, win, ghost_elo, ghost_matches.len(), &self.rating);
+                    self.agents.get_mut(token).unwrap().rollman_elo = new_a;
+                    self.agents.get_mut(&n.rollman).unwrap().rollman_elo = new_b;
+                }
+            }
+            Role::Ghost => {
+                let rollman_elo = opponent_agent.rollman_elo;
+                let rollman_matches = self
+                    .matches_with_rollman
+                    .get(opponent)
+                    .map_or::<&[_], _>(&[], Vec::as_slice);
+                let worst = rollman_matches.iter().map(|(_, n)| n.ghost_score).min();
+
+                for (_, n) in rollman_matches {
+                    if n.ghost == token {
+                        continue;
+                    }
+                    let win = match policy {
+                        FailurePolicy::WorstScore if Some(n.ghost_score) == worst => 0.5,
+                        _ => 0.0,
+                    };
+                    let a = self.agents.get(token).unwrap().ghost_elo;
+                    let b = self.agents.get(&n.ghost).unwrap().ghost_elo;
+                    let (new_a, new_b) =
+                        elo(a, b, win, rollman_elo, rollman_matches.len(), &self.rating);
+                    self.agents.get_mut(token).unwrap().ghost_elo = new_a;
+                    self.agents.get_mut(&n.ghost).unwrap().ghost_elo = new_b;
+                }
+            }
+        }
+    }
+
+    pub fn load(storage: &mut dyn Storage, rating: &RatingConfig) -> Result<Self> {
+        let mut stats = storage.load()?;
+        stats.rating = rating.clone();
 
         for a in stats.agents.values_mut() {
-            a.rollman_elo = ELO_BASE;
-            a.ghost_elo = ELO_BASE;
+            a.rollman_elo = rating.elo_base;
+            a.ghost_elo = rating.elo_base;
             a.rollman_count = 0;
             a.ghost_count = 0;
             a.rollman_time = u32::MAX;
             a.ghost_time = u32::MAX;
+            a.rollman_since = None;
+            a.ghost_since = None;
         }
 
-        let mut matches = BTreeMap::new();
-        std::mem::swap(&mut stats.matches, &mut matches);
-        let mut matches = matches.into_iter().collect::<Vec<_>>();
+        let mut records = stats
+            .matches
+            .iter()
+            .map(|(id, m)| Rated::Match(*id, m.clone()))
+            .chain(stats.agents.iter().flat_map(|(token, a)| {
+                a.failure
+                    .values()
+                    .map(|f| Rated::Failure(token.clone(), f.clone()))
+            }))
+            .collect::<Vec<_>>();
 
         let rng = &mut rand::rng();
-        matches.shuffle(rng);
+        records.shuffle(rng);
 
-        for (id, m) in matches.clone() {
-            stats.add_match(id, m);
+        for record in records.clone() {
+            stats.rate(record);
         }
 
-        stats.matches.clear();
         stats.matches_with_rollman.clear();
         stats.matches_with_ghost.clear();
         stats.count_rollman_ghost.clear();
@@ -137,22 +279,23 @@ impl Stats {
             a.ghost_count = 0;
         }
 
-        matches.shuffle(rng);
+        records.shuffle(rng);
 
-        for (id, m) in matches {
-            stats.add_match(id, m);
+        for record in records {
+            stats.rate(record);
         }
 
         Ok(stats)
     }
 
-    pub fn save(&self) -> Result<()> {
-        let storage = File::create("storage.json")?;
-        let buf = BufWriter::new(storage);
-        serde_json::to_writer(buf, self)?;
+    pub fn save(&self, storage: &mut dyn Storage, report: &ReportConfig) -> Result<()> {
+        storage.save(self)?;
+        self.save_reports(Path::new("."), report)
+    }
 
-        let elo = File::create("elo.csv")?;
-        let mut buf = BufWriter::new(elo);
+    /// Writes `elo.csv` and the HTML pages into `out`.
+    pub fn save_reports(&self, out: &Path, report: &ReportConfig) -> Result<()> {
+        let mut buf = AtomicFile::create(out.join("elo.csv"))?;
         writeln!(&mut buf, "user,name,version,rollman_elo,ghost_elo")?;
         for agent in self.agents.values() {
             writeln!(
@@ -161,9 +304,9 @@ impl Stats {
                 agent.user, agent.name, agent.version, agent.rollman_elo, agent.ghost_elo
             )?;
         }
+        buf.commit()?;
 
-        let html = File::create("ranking.html")?;
-        let mut buf = BufWriter::new(html);
+        let mut buf = AtomicFile::create(out.join("ranking.html"))?;
 
         let mut rollmen: Vec<_> = self
             .agents
@@ -172,7 +315,6 @@ impl Stats {
             .collect();
         let mut ghosts: Vec<_> = self.agents.iter().filter(|(_, a)| a.can_ghost()).collect();
 
-        let last_match = self.matches.last_key_value().map(|(k, _)| *k).unwrap_or(0);
         rollmen.sort_by_key(|(_, a)| Reverse(OrderedFloat(a.rollman_elo)));
         ghosts.sort_by_key(|(_, a)| Reverse(OrderedFloat(a.ghost_elo)));
 
@@ -211,6 +353,8 @@ impl Stats {
       <div>
         <a href="https://www.saiblo.net/game/42">RollMan (Saiblo)</a>
         <a href="https://www.saiblo.net/game/42?id=2">对局列表</a>
+        <a href="users.html">用户统计</a>
+        <a href="seasons.html">往期</a>
         <a href="https://github.com/ouuan/rollman-elo">Repo</a>
         最后更新于 {}
       </div>
@@ -245,6 +389,8 @@ impl Stats {
             <th>Elo</th>
             <th title="对局数">#M</th>
             <th title="近{RECENT_MATCH_COUNT}局中的fail数">F%</th>
+            <th title="平均得分 ± 标准差">Score</th>
+            <th title="近{TREND_WINDOW}局的得分趋势（每局）">Trend</th>
             <th>Token</th>
         </tr>"#
         )?;
@@ -256,7 +402,7 @@ impl Stats {
                 .unwrap()
                 .iter()
                 .map(|(id, _)| (Reverse(*id), false))
-                .chain(agent.failure.iter().map(|id| (Reverse(*id), true)))
+                .chain(agent.failure.keys().map(|id| (Reverse(*id), true)))
                 .collect::<Vec<_>>();
             let fail_count = if matches.len() > RECENT_MATCH_COUNT {
                 matches
@@ -274,13 +420,12 @@ impl Stats {
         <tr{}>
           <td></td>
           <td><a href="https://www.saiblo.net/user/{}">{}</a></td>
-          <td>{}</td><td>{}</td><td {}>{:.0}</td><td>{}</td><td>{}</td>
+          <td>{}</td><td>{}</td><td {}>{:.0}</td><td>{}</td><td title="{}">{}</td>{}
           <td><button onclick="copy('{}')">token</button>
         </tr>"#,
                 row_style(
                     agent.rollman_count < ghosts.len(),
-                    agent.rollman_time,
-                    last_match,
+                    self.recency(agent.rollman_time, agent.rollman_since, report),
                     rollman_users.insert(agent.user.clone())
                 ),
                 escape_html(&agent.user),
@@ -290,7 +435,9 @@ impl Stats {
                 rating_color(agent.rollman_elo),
                 agent.rollman_elo,
                 agent.rollman_count,
+                failure_breakdown(agent.failure.values()),
                 fail_count,
+                score_cells(self.rollman_score_stats(token).as_ref()),
                 token,
             )?;
         }
@@ -316,6 +463,8 @@ impl Stats {
             <th>Elo</th>
             <th title="对局数">#M</th>
             <th title="近{RECENT_MATCH_COUNT}局中的fail数">F%</th>
+            <th title="平均得分 ± 标准差">Score</th>
+            <th title="近{TREND_WINDOW}局的得分趋势（每局）">Trend</th>
             <th>Token</th>
         </tr>"#
         )?;
@@ -329,7 +478,7 @@ impl Stats {
                 .unwrap()
                 .iter()
                 .map(|(id, _)| (Reverse(*id), false))
-                .chain(agent.failure.iter().map(|id| (Reverse(*id), true)))
+                .chain(agent.failure.keys().map(|id| (Reverse(*id), true)))
                 .collect::<Vec<_>>();
             let fail_count = if matches.len() > RECENT_MATCH_COUNT {
                 matches
@@ -347,13 +496,12 @@ impl Stats {
         <tr{}>
           <td></td>
           <td><a href="https://www.saiblo.net/user/{}">{}</a></td>
-          <td>{}</td><td>{}</td><td {}>{:.0}</td><td>{}</td><td>{}</td>
+          <td>{}</td><td>{}</td><td {}>{:.0}</td><td>{}</td><td title="{}">{}</td>{}
           <td><button onclick="copy('{}')">token</button>
         </tr>"#,
                 row_style(
                     agent.ghost_count < rollmen.len(),
-                    agent.ghost_time,
-                    last_match,
+                    self.recency(agent.ghost_time, agent.ghost_since, report),
                     ghost_users.insert(agent.user.clone())
                 ),
                 escape_html(&agent.user),
@@ -363,7 +511,9 @@ impl Stats {
                 rating_color(agent.ghost_elo),
                 agent.ghost_elo,
                 agent.ghost_count,
+                failure_breakdown(agent.failure.values()),
                 fail_count,
+                score_cells(self.ghost_score_stats(token).as_ref()),
                 token,
             )?;
         }
@@ -382,12 +532,47 @@ impl Stats {
 
 </html>"#
         )?;
+        buf.commit()?;
+
+        self.save_users(out)?;
+        self.save_seasons(out)?;
 
         Ok(())
     }
 }
 
-fn escape_html(s: &str) -> String {
+impl Stats {
+    /// e.g. "alice/pacer v2 (token)"
+    pub fn describe(&self, token: &str) -> String {
+        match self.agents.get(token) {
+            Some(a) => format!("{}/{} v{} ({token})", a.user, a.name, a.version),
+            None => token.to_string(),
+        }
+    }
+
+    /// From 1 for an agent that first played in a role just now, down to 0 for one that first
+    /// played `recent_hours` ago, or `recent_threshold` matches ago if the time is unknown.
+    fn recency(
+        &self,
+        first_id: u32,
+        first_time: Option<DateTime<Utc>>,
+        report: &ReportConfig,
+    ) -> f32 {
+        let ratio = match first_time {
+            Some(time) => {
+                (Utc::now() - time).num_seconds() as f32
+                    / report.recent_duration().num_seconds() as f32
+            }
+            None => {
+                let last = self.matches.last_key_value().map_or(0, |(id, _)| *id);
+                last.saturating_sub(first_id) as f32 / report.recent_threshold as f32
+            }
+        };
+        (1.0 - ratio).clamp(0.0, 1.0)
+    }
+}
+
+pub fn escape_html(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
@@ -395,12 +580,12 @@ fn escape_html(s: &str) -> String {
         .replace('\'', "&#39;")
 }
 
-fn row_style(unreliable: bool, time: u32, last: u32, new_user: bool) -> String {
+fn row_style(unreliable: bool, recency: f32, new_user: bool) -> String {
     let mut style = Vec::new();
-    if last - time < RECENT_THRESHOLD {
+    if recency > 0.0 {
         style.push(format!(
             "background-color: rgb(240, 136, 62, {});",
-            0.5 * (RECENT_THRESHOLD + time - last) as f32 / RECENT_THRESHOLD as f32
+            0.5 * recency
         ));
     }
 
@@ -423,7 +608,7 @@ fn row_style(unreliable: bool, time: u32, last: u32, new_user: bool) -> String {
 }
 
 // https://github.com/vfleaking/uoj/blob/04061436e53ac7390b34aac6760e03fc6ad6b39f/web/public/js/uoj.js#L146
-fn rating_color(rating: f32) -> String {
+pub fn rating_color(rating: f32) -> String {
     let rating = rating.clamp(300.0, 2500.0);
     let (h, s, v) = if rating < 1500.0 {
         const H: f32 = 300.0 - (1500.0 - 850.0) * 300.0 / 1650.0;
@@ -465,26 +650,37 @@ pub struct Agent {
     pub rollman_time: u32,
     #[serde(skip)]
     pub ghost_time: u32,
-    #[serde(default)]
-    pub failure: BTreeSet<u32>,
+    /// Creation time of the match at `rollman_time`, if known.
+    #[serde(skip)]
+    pub rollman_since: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub ghost_since: Option<DateTime<Utc>>,
+    pub failure: BTreeMap<u32, Failure>,
 }
 
 impl Agent {
+    /// Ratings are set by `Stats::add_agent` and `Stats::load`.
     pub fn new(user: String, name: String, version: u32) -> Self {
         Self {
             user,
             name,
             version,
-            rollman_elo: ELO_BASE,
-            ghost_elo: ELO_BASE,
+            rollman_elo: 0.0,
+            ghost_elo: 0.0,
             rollman_count: 0,
             ghost_count: 0,
             rollman_time: u32::MAX,
             ghost_time: u32::MAX,
-            failure: BTreeSet::new(),
+            rollman_since: None,
+            ghost_since: None,
+            failure: BTreeMap::new(),
         }
     }
 
+    pub fn first_time(&self) -> u32 {
+        self.rollman_time.min(self.ghost_time)
+    }
+
     pub fn can_rollman(&self) -> bool {
         self.rollman_count > self.failure.len().saturating_sub(50) * 10
     }
@@ -500,4 +696,121 @@ pub struct Match {
     pub ghost: String,
     pub rollman_score: i16,
     pub ghost_score: i16,
+    pub logic_version: u16,
+    /// The Saiblo metadata below is missing for matches collected before it was stored.
+    pub created_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub room_id: Option<u32>,
+    /// Username of whoever created the match.
+    pub creator: Option<String>,
+}
+
+/// Anything that takes part in the rating, replayed in random order when loading.
+#[derive(Clone)]
+enum Rated {
+    Match(u32, Match),
+    Failure(String, Failure),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum DrawKind {
+    /// Both sides ended normally with equal scores.
+    Tie,
+    DoubleFailure {
+        rollman: FailureKind,
+        ghost: FailureKind,
+    },
+}
+
+/// A match without a winner, which is not rated.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Draw {
+    pub rollman: String,
+    pub ghost: String,
+    pub rollman_score: i16,
+    pub ghost_score: i16,
+    pub kind: DrawKind,
+    /// Whether `rollman` and `ghost` are known to be the roles.
+    pub roles_known: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rollmen `r1` and `r2` scored 5 and 3 against the ghost `g`, and `r3` played nothing yet.
+    fn stats(policy: FailurePolicy) -> Stats {
+        let mut stats = Stats::default();
+        stats.rating.failure_policy = policy;
+        for token in ["r1", "r2", "r3", "g"] {
+            stats.add_agent(token, token.to_string(), token.to_string(), 1);
+        }
+        for (id, rollman, score) in [(1, "r1", 5), (2, "r2", 3)] {
+            let m = Match {
+                rollman: rollman.to_string(),
+                ghost: "g".to_string(),
+                rollman_score: score,
+                ghost_score: 1,
+                logic_version: 0,
+                created_at: None,
+                finished_at: None,
+                room_id: None,
+                creator: None,
+            };
+            stats.add_match(id, m);
+        }
+        stats
+    }
+
+    fn rollman_elos(stats: &Stats) -> [f32; 3] {
+        ["r1", "r2", "r3"].map(|token| stats.agents[token].rollman_elo)
+    }
+
+    fn fail(stats: &mut Stats, role: Option<Role>) {
+        let failure = Failure {
+            kind: FailureKind::TimeLimit,
+            role,
+            opponent: Some("g".to_string()),
+        };
+        assert!(stats.add_failure(3, "r3", failure));
+    }
+
+    /// The rollman ratings after `r3` plays against `r1` and then `r2`, with the given results.
+    fn expected(stats: &Stats, wins: [f32; 2]) -> [f32; 3] {
+        let [mut r1, mut r2, mut r3] = rollman_elos(stats);
+        let ghost_elo = stats.agents["g"].ghost_elo;
+        (r3, r1) = elo(r3, r1, wins[0], ghost_elo, 2, &stats.rating);
+        (r3, r2) = elo(r3, r2, wins[1], ghost_elo, 2, &stats.rating);
+        [r1, r2, r3]
+    }
+
+    #[test]
+    fn failure_as_loss() {
+        let mut stats = stats(FailurePolicy::Loss);
+        let expected = expected(&stats, [0.0, 0.0]);
+        fail(&mut stats, Some(Role::Rollman));
+        assert_eq!(rollman_elos(&stats), expected);
+        assert!(expected[2] < stats.rating.elo_base);
+    }
+
+    #[test]
+    fn failure_as_worst_score() {
+        let mut stats = stats(FailurePolicy::WorstScore);
+        // Ties with `r2`, which got the worst score against `g`.
+        let expected = expected(&stats, [0.0, 0.5]);
+        fail(&mut stats, Some(Role::Rollman));
+        assert_eq!(rollman_elos(&stats), expected);
+    }
+
+    #[test]
+    fn failure_without_role_is_not_rated() {
+        for policy in [FailurePolicy::Loss, FailurePolicy::WorstScore] {
+            let mut stats = stats(policy);
+            let before = rollman_elos(&stats);
+            let ghost_elo = stats.agents["g"].ghost_elo;
+            fail(&mut stats, None);
+            assert_eq!(rollman_elos(&stats), before);
+            assert_eq!(stats.agents["g"].ghost_elo, ghost_elo);
+        }
+    }
 }
@@ -0,0 +1,834 @@
+use crate::analytics::MatchAnalytics;
+use crate::atomic::AtomicFile;
+use crate::config::StorageConfig;
+use crate::cursor::FetchCursor;
+use crate::failure::*;
+use crate::migrate::*;
+use crate::season::*;
+use crate::stats::*;
+use chrono::{DateTime, Local, Utc};
+use color_eyre::eyre::{bail, Result};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Where the collected data lives between runs.
+///
+/// `load` and `save` handle the whole dataset. The `insert_*` methods are called by `fetch` as
+/// soon as something new is collected, so backends that support it can persist incrementally.
+pub trait Storage {
+    /// Loads the stored data. Ratings are not computed.
+    fn load(&mut self) -> Result<Stats>;
+
+    fn save(&mut self, stats: &Stats) -> Result<()>;
+
+    fn insert_agent(&mut self, _token: &str, _agent: &Agent) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_match(&mut self, _id: u32, _m: &Match) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_draw(&mut self, _id: u32, _draw: &Draw) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_failure(&mut self, _id: u32, _token: &str, _failure: &Failure) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_analytics(&mut self, _id: u32, _analytics: &MatchAnalytics) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called by `fetch` whenever more of the match list has been listed.
+    fn save_cursor(&mut self, _cursor: &FetchCursor) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called by `fetch` along with `save_cursor`.
+    fn save_pending(&mut self, _pending: &BTreeMap<u32, DateTime<Utc>>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the matches of `old_version`, if any, have been moved to `stats.seasons` and
+    /// `stats` has started over with a new logic version.
+    fn archive(&mut self, _old_version: u16, _stats: &Stats) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens `storage.json`-like paths as `JsonStorage` and `*.sqlite`/`*.db` as `SqliteStorage`.
+pub fn open(config: &StorageConfig) -> Result<Box<dyn Storage>> {
+    let path = config.path.as_path();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => Ok(Box::new(JsonStorage::new(path, config.backup_count))),
+        Some("sqlite" | "db") => Ok(Box::new(SqliteStorage::open(path)?)),
+        _ => bail!("unknown storage type: {}", path.display()),
+    }
+}
+
+pub struct JsonStorage {
+    path: PathBuf,
+    backup_count: usize,
+}
+
+impl JsonStorage {
+    pub fn new(path: impl Into<PathBuf>, backup_count: usize) -> Self {
+        Self {
+            path: path.into(),
+            backup_count,
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&mut self) -> Result<Stats> {
+        let storage = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(_) => return Ok(Stats::default()),
+        };
+        let buf = BufReader::new(storage);
+        let mut value = serde_json::from_reader(buf)?;
+        migrate_json(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    fn save(&mut self, stats: &Stats) -> Result<()> {
+        #[derive(Serialize)]
+        struct Versioned<'a> {
+            schema_version: u32,
+            #[serde(flatten)]
+            stats: &'a Stats,
+        }
+
+        let mut storage = AtomicFile::create(&self.path)?;
+        let versioned = Versioned {
+            schema_version: JSON_SCHEMA_VERSION,
+            stats,
+        };
+        serde_json::to_writer(&mut storage, &versioned)?;
+        storage.commit_with_backups(self.backup_count)
+    }
+}
+
+pub struct SqliteStorage {
+    conn: Connection,
+    persisted: Persisted,
+}
+
+/// The keys of the rows already in the database, so `save` only writes new ones. Agents, matches,
+/// draws, failures and analytics never change once collected.
+#[derive(Default)]
+struct Persisted {
+    agents: HashSet<String>,
+    matches: HashSet<u32>,
+    draws: HashSet<u32>,
+    /// By match id, as only one side of a match can fail alone.
+    failures: HashSet<u32>,
+    analytics: HashSet<u32>,
+}
+
+impl Persisted {
+    fn of(stats: &Stats) -> Self {
+        Self {
+            agents: stats.agents.keys().cloned().collect(),
+            matches: stats.matches.keys().copied().collect(),
+            draws: stats.draws.keys().copied().collect(),
+            failures: stats
+                .agents
+                .values()
+                .flat_map(|a| a.failure.keys().copied())
+                .collect(),
+            analytics: stats.analytics.keys().copied().collect(),
+        }
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.agents.extend(other.agents);
+        self.matches.extend(other.matches);
+        self.draws.extend(other.draws);
+        self.failures.extend(other.failures);
+        self.analytics.extend(other.analytics);
+    }
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        migrate_sqlite(&mut conn)?;
+        Ok(Self {
+            conn,
+            persisted: Persisted::default(),
+        })
+    }
+
+    fn get_meta(&self, key: &str) -> Result<Option<i64>> {
+        Ok(self
+            .conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set_meta(conn: &Connection, key: &str, value: i64) -> Result<()> {
+        conn.prepare_cached("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?
+            .execute(params![key, value])?;
+        Ok(())
+    }
+
+    fn write_cursor(conn: &Connection, cursor: &FetchCursor) -> Result<()> {
+        conn.prepare_cached("DELETE FROM fetch_cursor")?
+            .execute([])?;
+        for (first, last) in cursor.ranges() {
+            conn.prepare_cached("INSERT INTO fetch_cursor (first_id, last_id) VALUES (?1, ?2)")?
+                .execute(params![first, last])?;
+        }
+        Ok(())
+    }
+
+    fn write_pending(conn: &Connection, pending: &BTreeMap<u32, DateTime<Utc>>) -> Result<()> {
+        conn.prepare_cached("DELETE FROM pending_matches")?
+            .execute([])?;
+        for (id, first_seen) in pending {
+            conn.prepare_cached("INSERT INTO pending_matches (id, first_seen) VALUES (?1, ?2)")?
+                .execute(params![id, first_seen])?;
+        }
+        Ok(())
+    }
+
+    fn write_agent(conn: &Connection, token: &str, agent: &Agent) -> Result<()> {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO agents (token, user, name, version) VALUES (?1, ?2, ?3, ?4)",
+        )?
+        .execute(params![token, agent.user, agent.name, agent.version])?;
+        Ok(())
+    }
+
+    fn write_match(conn: &Connection, id: u32, m: &Match) -> Result<()> {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO matches
+             (id, rollman, ghost, rollman_score, ghost_score,
+              logic_version, created_at, finished_at, room_id, creator)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?
+        .execute(params![
+            id,
+            m.rollman,
+            m.ghost,
+            m.rollman_score,
+            m.ghost_score,
+            m.logic_version,
+            m.created_at,
+            m.finished_at,
+            m.room_id,
+            m.creator,
+        ])?;
+        Ok(())
+    }
+
+    fn write_draw(conn: &Connection, id: u32, draw: &Draw) -> Result<()> {
+        let (rollman_failure, ghost_failure) = draw_failures_to_sql(draw);
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO draws
+             (id, rollman, ghost, rollman_score, ghost_score,
+              rollman_failure, ghost_failure, double_failure, roles_known)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?
+        .execute(params![
+            id,
+            draw.rollman,
+            draw.ghost,
+            draw.rollman_score,
+            draw.ghost_score,
+            rollman_failure,
+            ghost_failure,
+            matches!(draw.kind, DrawKind::DoubleFailure { .. }),
+            draw.roles_known,
+        ])?;
+        Ok(())
+    }
+
+    fn write_failure(conn: &Connection, id: u32, token: &str, failure: &Failure) -> Result<()> {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO failures (match_id, token, kind, role, opponent)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?
+        .execute(params![
+            id,
+            token,
+            kind_to_sql(&failure.kind),
+            failure.role.map(|r| r.to_string()),
+            failure.opponent,
+        ])?;
+        Ok(())
+    }
+
+    fn write_analytics(conn: &Connection, id: u32, analytics: &MatchAnalytics) -> Result<()> {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO match_analytics
+             (id, length, lead_changes, final_score_at, rollman_distance, ghost_distance,
+              score_timeline, rollman_actions, ghost_actions)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?
+        .execute(params![
+            id,
+            analytics.length,
+            analytics.lead_changes,
+            analytics.final_score_at,
+            analytics.rollman_distance,
+            analytics.ghost_distance,
+            serde_json::to_string(&analytics.score_timeline)?,
+            serde_json::to_string(&analytics.rollman_actions)?,
+            serde_json::to_string(&analytics.ghost_actions)?,
+        ])?;
+        Ok(())
+    }
+
+    fn load_analytics(&self, stats: &mut Stats) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, length, lead_changes, final_score_at, rollman_distance, ghost_distance,
+                    score_timeline, rollman_actions, ghost_actions
+             FROM match_analytics",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let analytics = MatchAnalytics {
+                length: row.get(1)?,
+                lead_changes: row.get(2)?,
+                final_score_at: row.get(3)?,
+                rollman_distance: row.get(4)?,
+                ghost_distance: row.get(5)?,
+                score_timeline: serde_json::from_str(&row.get::<_, String>(6)?)?,
+                rollman_actions: serde_json::from_str(&row.get::<_, String>(7)?)?,
+                ghost_actions: serde_json::from_str(&row.get::<_, String>(8)?)?,
+            };
+            stats.analytics.insert(row.get(0)?, analytics);
+        }
+        Ok(())
+    }
+
+    fn write_season(conn: &Connection, logic_version: u16, season: &Season) -> Result<()> {
+        for (token, a) in &season.agents {
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO season_agents
+                 (logic_version, token, user, name, version, rollman_elo, ghost_elo,
+                  rollman_count, ghost_count, failure_count)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                logic_version,
+                token,
+                a.user,
+                a.name,
+                a.version,
+                a.rollman_elo,
+                a.ghost_elo,
+                a.rollman_count as i64,
+                a.ghost_count as i64,
+                a.failure_count as i64,
+            ])?;
+        }
+        for (id, m) in &season.matches {
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO season_matches
+                 (id, logic_version, rollman, ghost, rollman_score, ghost_score,
+                  created_at, finished_at, room_id, creator)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                id,
+                logic_version,
+                m.rollman,
+                m.ghost,
+                m.rollman_score,
+                m.ghost_score,
+                m.created_at,
+                m.finished_at,
+                m.room_id,
+                m.creator,
+            ])?;
+        }
+        for (id, draw) in &season.draws {
+            let (rollman_failure, ghost_failure) = draw_failures_to_sql(draw);
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO season_draws
+                 (id, logic_version, rollman, ghost, rollman_score, ghost_score,
+                  rollman_failure, ghost_failure, double_failure, roles_known)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                id,
+                logic_version,
+                draw.rollman,
+                draw.ghost,
+                draw.rollman_score,
+                draw.ghost_score,
+                rollman_failure,
+                ghost_failure,
+                matches!(draw.kind, DrawKind::DoubleFailure { .. }),
+                draw.roles_known,
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn load_seasons(&self, stats: &mut Stats) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT logic_version, token, user, name, version, rollman_elo, ghost_elo,
+                    rollman_count, ghost_count, failure_count
+             FROM season_agents",
+        )?;
+        let agents = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u16>(0)?,
+                row.get::<_, String>(1)?,
+                SeasonAgent {
+                    user: row.get(2)?,
+                    name: row.get(3)?,
+                    version: row.get(4)?,
+                    rollman_elo: row.get(5)?,
+                    ghost_elo: row.get(6)?,
+                    rollman_count: row.get::<_, i64>(7)? as usize,
+                    ghost_count: row.get::<_, i64>(8)? as usize,
+                    failure_count: row.get::<_, i64>(9)? as usize,
+                },
+            ))
+        })?;
+        for agent in agents {
+            let (logic_version, token, agent) = agent?;
+            let season = stats.seasons.entry(logic_version).or_default();
+            season.agents.insert(token, agent);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, rollman, ghost, rollman_score, ghost_score,
+                    logic_version, created_at, finished_at, room_id, creator
+             FROM season_matches",
+        )?;
+        let matches = stmt.query_map([], |row| {
+            let m = match_from_row(row, 1)?;
+            Ok((m.logic_version, row.get(0)?, m))
+        })?;
+        for m in matches {
+            let (logic_version, id, m) = m?;
+            let season = stats.seasons.entry(logic_version).or_default();
+            season.matches.insert(id, m);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT logic_version, id, rollman, ghost, rollman_score, ghost_score,
+                    rollman_failure, ghost_failure, double_failure, roles_known
+             FROM season_draws",
+        )?;
+        let draws = stmt.query_map([], |row| {
+            Ok((row.get::<_, u16>(0)?, row.get(1)?, draw_from_row(row, 2)?))
+        })?;
+        for draw in draws {
+            let (logic_version, id, draw) = draw?;
+            let season = stats.seasons.entry(logic_version).or_default();
+            season.draws.insert(id, draw);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the columns `rollman, ghost, rollman_score, ghost_score, logic_version, created_at,
+/// finished_at, room_id, creator` starting at `start`.
+fn match_from_row(row: &Row, start: usize) -> rusqlite::Result<Match> {
+    Ok(Match {
+        rollman: row.get(start)?,
+        ghost: row.get(start + 1)?,
+        rollman_score: row.get(start + 2)?,
+        ghost_score: row.get(start + 3)?,
+        logic_version: row.get(start + 4)?,
+        created_at: row.get(start + 5)?,
+        finished_at: row.get(start + 6)?,
+        room_id: row.get(start + 7)?,
+        creator: row.get(start + 8)?,
+    })
+}
+
+/// Reads the columns `rollman, ghost, rollman_score, ghost_score, rollman_failure,
+/// ghost_failure, double_failure, roles_known` starting at `start`.
+fn draw_from_row(row: &Row, start: usize) -> rusqlite::Result<Draw> {
+    let kind = if row.get(start + 6)? {
+        DrawKind::DoubleFailure {
+            rollman: kind_from_sql(row.get(start + 4)?),
+            ghost: kind_from_sql(row.get(start + 5)?),
+        }
+    } else {
+        DrawKind::Tie
+    };
+    Ok(Draw {
+        rollman: row.get(start)?,
+        ghost: row.get(start + 1)?,
+        rollman_score: row.get(start + 2)?,
+        ghost_score: row.get(start + 3)?,
+        kind,
+        roles_known: row.get(start + 7)?,
+    })
+}
+
+fn draw_failures_to_sql(draw: &Draw) -> (Option<String>, Option<String>) {
+    match &draw.kind {
+        DrawKind::Tie => (None, None),
+        DrawKind::DoubleFailure { rollman, ghost } => (kind_to_sql(rollman), kind_to_sql(ghost)),
+    }
+}
+
+fn kind_to_sql(kind: &FailureKind) -> Option<String> {
+    match kind {
+        FailureKind::Unknown => None,
+        kind => Some(kind.to_string()),
+    }
+}
+
+fn kind_from_sql(kind: Option<String>) -> FailureKind {
+    FailureKind::from_end_state(kind.as_deref())
+}
+
+fn role_from_sql(role: Option<String>) -> Option<Role> {
+    match role.as_deref() {
+        Some("rollman") => Some(Role::Rollman),
+        Some("ghost") => Some(Role::Ghost),
+        _ => None,
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Stats> {
+        let mut stats = Stats {
+            logic_version: self.get_meta("logic_version")?.unwrap_or(0) as u16,
+            ..Default::default()
+        };
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT token, user, name, version FROM agents")?;
+        let agents = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Agent::new(row.get(1)?, row.get(2)?, row.get(3)?),
+            ))
+        })?;
+        for agent in agents {
+            let (token, agent) = agent?;
+            stats.agents.insert(token, agent);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, rollman, ghost, rollman_score, ghost_score,
+                    logic_version, created_at, finished_at, room_id, creator
+             FROM matches",
+        )?;
+        let matches = stmt.query_map([], |row| Ok((row.get(0)?, match_from_row(row, 1)?)))?;
+        for m in matches {
+            let (id, m) = m?;
+            stats.matches.insert(id, m);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, rollman, ghost, rollman_score, ghost_score,
+                    rollman_failure, ghost_failure, double_failure, roles_known
+             FROM draws",
+        )?;
+        let draws = stmt.query_map([], |row| Ok((row.get(0)?, draw_from_row(row, 1)?)))?;
+        for draw in draws {
+            let (id, draw) = draw?;
+            stats.draws.insert(id, draw);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT match_id, token, kind, role, opponent FROM failures")?;
+        let failures = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+                Failure {
+                    kind: kind_from_sql(row.get(2)?),
+                    role: role_from_sql(row.get(3)?),
+                    opponent: row.get(4)?,
+                },
+            ))
+        })?;
+        for failure in failures {
+            let (id, token, failure) = failure?;
+            if let Some(agent) = stats.agents.get_mut(&token) {
+                agent.failure.insert(id, failure);
+            }
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT first_id, last_id FROM fetch_cursor")?;
+        let ranges = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for range in ranges {
+            let (first, last) = range?;
+            stats.fetch_cursor.insert(first, last);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, first_seen FROM pending_matches")?;
+        let pending = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for m in pending {
+            let (id, first_seen) = m?;
+            stats.pending.insert(id, first_seen);
+        }
+
+        self.load_seasons(&mut stats)?;
+        self.load_analytics(&mut stats)?;
+
+        self.persisted = Persisted::of(&stats);
+        Ok(stats)
+    }
+
+    /// Writes the rows that are not in the database yet, and a rating snapshot.
+    fn save(&mut self, stats: &Stats) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let persisted = &self.persisted;
+        let mut new = Persisted::default();
+
+        Self::set_meta(&tx, "logic_version", stats.logic_version.into())?;
+        Self::write_pending(&tx, &stats.pending)?;
+        Self::write_cursor(&tx, &stats.fetch_cursor)?;
+
+        let time = Local::now().to_rfc3339();
+        for (token, agent) in &stats.agents {
+            if !persisted.agents.contains(token) {
+                Self::write_agent(&tx, token, agent)?;
+                new.agents.insert(token.clone());
+            }
+            for (&id, failure) in &agent.failure {
+                if !persisted.failures.contains(&id) {
+                    Self::write_failure(&tx, id, token, failure)?;
+                    new.failures.insert(id);
+                }
+            }
+            if agent.rollman_count + agent.ghost_count > 0 {
+                tx.prepare_cached(
+                    "INSERT OR REPLACE INTO rating_snapshots
+                     (time, token, rollman_elo, ghost_elo, rollman_count, ghost_count,
+                      logic_version)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )?
+                .execute(params![
+                    time,
+                    token,
+                    agent.rollman_elo,
+                    agent.ghost_elo,
+                    agent.rollman_count as i64,
+                    agent.ghost_count as i64,
+                    stats.logic_version,
+                ])?;
+            }
+        }
+        for (&id, m) in &stats.matches {
+            if !persisted.matches.contains(&id) {
+                Self::write_match(&tx, id, m)?;
+                new.matches.insert(id);
+            }
+        }
+        for (&id, draw) in &stats.draws {
+            if !persisted.draws.contains(&id) {
+                Self::write_draw(&tx, id, draw)?;
+                new.draws.insert(id);
+            }
+        }
+        for (&id, analytics) in &stats.analytics {
+            if !persisted.analytics.contains(&id) {
+                Self::write_analytics(&tx, id, analytics)?;
+                new.analytics.insert(id);
+            }
+        }
+        for (logic_version, season) in &stats.seasons {
+            let archived: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM season_agents WHERE logic_version = ?1)",
+                [logic_version],
+                |row| row.get(0),
+            )?;
+            if !archived {
+                Self::write_season(&tx, *logic_version, season)?;
+            }
+        }
+
+        tx.commit()?;
+        self.persisted.extend(new);
+        Ok(())
+    }
+
+    fn insert_agent(&mut self, token: &str, agent: &Agent) -> Result<()> {
+        if !self.persisted.agents.contains(token) {
+            Self::write_agent(&self.conn, token, agent)?;
+            self.persisted.agents.insert(token.to_string());
+        }
+        Ok(())
+    }
+
+    fn insert_match(&mut self, id: u32, m: &Match) -> Result<()> {
+        Self::write_match(&self.conn, id, m)?;
+        self.persisted.matches.insert(id);
+        Ok(())
+    }
+
+    fn insert_draw(&mut self, id: u32, draw: &Draw) -> Result<()> {
+        Self::write_draw(&self.conn, id, draw)?;
+        self.persisted.draws.insert(id);
+        Ok(())
+    }
+
+    fn insert_failure(&mut self, id: u32, token: &str, failure: &Failure) -> Result<()> {
+        Self::write_failure(&self.conn, id, token, failure)?;
+        self.persisted.failures.insert(id);
+        Ok(())
+    }
+
+    fn insert_analytics(&mut self, id: u32, analytics: &MatchAnalytics) -> Result<()> {
+        Self::write_analytics(&self.conn, id, analytics)?;
+        self.persisted.analytics.insert(id);
+        Ok(())
+    }
+
+    fn save_pending(&mut self, pending: &BTreeMap<u32, DateTime<Utc>>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        Self::write_pending(&tx, pending)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_cursor(&mut self, cursor: &FetchCursor) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        Self::write_cursor(&tx, cursor)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn archive(&mut self, old_version: u16, stats: &Stats) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        if let Some(season) = stats.seasons.get(&old_version) {
+            Self::write_season(&tx, old_version, season)?;
+        }
+        // Rating snapshots are kept, by logic version.
+        tx.execute_batch(
+            "DELETE FROM failures;
+             DELETE FROM draws;
+             DELETE FROM matches;
+             DELETE FROM agents;
+             DELETE FROM meta;",
+        )?;
+        Self::set_meta(&tx, "logic_version", stats.logic_version.into())?;
+        Self::write_pending(&tx, &stats.pending)?;
+        Self::write_cursor(&tx, &stats.fetch_cursor)?;
+        tx.commit()?;
+        // Analytics are kept for archived matches too.
+        self.persisted = Persisted {
+            analytics: std::mem::take(&mut self.persisted.analytics),
+            ..Default::default()
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_match(id: u32) -> (u32, Match) {
+        let m = Match {
+            rollman: "a".to_string(),
+            ghost: "b".to_string(),
+            rollman_score: 7,
+            ghost_score: 2,
+            logic_version: 5,
+            created_at: None,
+            finished_at: None,
+            room_id: None,
+            creator: None,
+        };
+        (id, m)
+    }
+
+    #[test]
+    fn sqlite_save_writes_only_new_rows() {
+        let path =
+            std::env::temp_dir().join(format!("rollman-elo-save-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut storage = SqliteStorage::open(&path).unwrap();
+
+        let mut stats = Stats::default();
+        stats.add_agent("a", "alice".to_string(), "pacer".to_string(), 1);
+        stats.add_agent("b", "bob".to_string(), "chaser".to_string(), 1);
+        let (id, m) = sample_match(10);
+        stats.add_match(id, m);
+        storage.save(&stats).unwrap();
+
+        // A saved row is not written again.
+        storage
+            .conn
+            .execute("UPDATE matches SET rollman_score = 0 WHERE id = 10", [])
+            .unwrap();
+        let (id, m) = sample_match(12);
+        stats.add_match(id, m);
+        storage.save(&stats).unwrap();
+
+        let stats = SqliteStorage::open(&path).unwrap().load().unwrap();
+        assert_eq!(stats.matches[&10].rollman_score, 0);
+        assert_eq!(stats.matches[&12].rollman_score, 7);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_reopen_after_archive() {
+        let path =
+            std::env::temp_dir().join(format!("rollman-elo-archive-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut storage = SqliteStorage::open(&path).unwrap();
+
+        let mut stats = Stats {
+            logic_version: 5,
+            ..Default::default()
+        };
+        stats.add_agent("a", "alice".to_string(), "pacer".to_string(), 1);
+        stats.add_agent("b", "bob".to_string(), "chaser".to_string(), 1);
+        let (id, m) = sample_match(10);
+        stats.add_match(id, m);
+        stats.fetch_cursor.insert(0, 10);
+        stats.pending.insert(11, chrono::Utc::now());
+        storage.save(&stats).unwrap();
+
+        stats.archive(6);
+        // Carried over by `fetch`.
+        stats.pending.insert(12, chrono::Utc::now());
+        storage.archive(5, &stats).unwrap();
+        // Dies before the next save.
+        drop(storage);
+
+        let stats = SqliteStorage::open(&path).unwrap().load().unwrap();
+        assert_eq!(stats.logic_version, 6);
+        assert!(stats.matches.is_empty() && stats.agents.is_empty());
+        assert_eq!(stats.seasons[&5].matches.len(), 1);
+        assert!(stats.fetch_cursor.ranges().is_empty());
+        assert_eq!(stats.pending.keys().copied().collect::<Vec<_>>(), [12]);
+
+        let conn = Connection::open(&path).unwrap();
+        let snapshots: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM rating_snapshots WHERE logic_version = 5",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(snapshots, 2);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,540 @@
+use crate::atomic::AtomicFile;
+use crate::failure::*;
+use crate::score_stats::*;
+use crate::stats::*;
+use chrono::Local;
+use color_eyre::eyre::Result;
+use ordered_float::OrderedFloat;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+pub struct User<'a> {
+    pub name: &'a str,
+    /// All code entities of the user, ordered by their first match.
+    pub timeline: Vec<(&'a str, &'a Agent)>,
+    pub best_rollman: Option<(&'a str, &'a Agent)>,
+    pub best_ghost: Option<(&'a str, &'a Agent)>,
+    /// The ratings of every code entity of the user in past seasons, then in `timeline`.
+    pub progression: Vec<RatingPoint<'a>>,
+}
+
+/// The ratings of a code entity at the end of a season, or now for the current one.
+pub struct RatingPoint<'a> {
+    pub logic_version: u16,
+    pub name: &'a str,
+    pub version: u32,
+    /// `None` if the entity has not played the role.
+    pub rollman_elo: Option<f32>,
+    pub ghost_elo: Option<f32>,
+}
+
+impl<'a> User<'a> {
+    fn new(
+        name: &'a str,
+        mut agents: Vec<(&'a str, &'a Agent)>,
+        mut progression: Vec<RatingPoint<'a>>,
+        logic_version: u16,
+    ) -> Self {
+        agents.sort_by_key(|(token, a)| (a.first_time(), a.version, *token));
+        let best_rollman = agents
+            .iter()
+            .filter(|(_, a)| a.can_rollman())
+            .max_by_key(|(_, a)| OrderedFloat(a.rollman_elo))
+            .copied();
+        let best_ghost = agents
+            .iter()
+            .filter(|(_, a)| a.can_ghost())
+            .max_by_key(|(_, a)| OrderedFloat(a.ghost_elo))
+            .copied();
+        progression.extend(agents.iter().map(|(_, a)| RatingPoint {
+            logic_version,
+            name: &a.name,
+            version: a.version,
+            rollman_elo: (a.rollman_count > 0).then_some(a.rollman_elo),
+            ghost_elo: (a.ghost_count > 0).then_some(a.ghost_elo),
+        }));
+        Self {
+            name,
+            timeline: agents,
+            best_rollman,
+            best_ghost,
+            progression,
+        }
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.timeline
+            .iter()
+            .map(|(_, a)| a.rollman_count + a.ghost_count)
+            .sum()
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.timeline.iter().map(|(_, a)| a.failure.len()).sum()
+    }
+
+    pub fn failure_rate(&self) -> f32 {
+        let failures = self.failure_count();
+        let total = self.match_count() + failures;
+        if total == 0 {
+            0.0
+        } else {
+            failures as f32 / total as f32
+        }
+    }
+}
+
+const CHART_WIDTH: f32 = 480.0;
+const CHART_HEIGHT: f32 = 120.0;
+
+/// An inline SVG with the rollman (blue) and ghost (red) ratings of `progression` from left to
+/// right. Empty if there are fewer than two points.
+fn progression_chart(progression: &[RatingPoint]) -> String {
+    let elos = progression
+        .iter()
+        .flat_map(|p| [p.rollman_elo, p.ghost_elo])
+        .flatten();
+    let (min, max) = elos.fold((f32::MAX, f32::MIN), |(min, max), elo| {
+        (min.min(elo), max.max(elo))
+    });
+    if progression.len() < 2 || min > max {
+        return String::new();
+    }
+    let range = (max - min).max(1.0);
+    let line = |elo: fn(&RatingPoint) -> Option<f32>, color: &str| {
+        let points: Vec<_> = progression
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let x = i as f32 * CHART_WIDTH / (progression.len() - 1) as f32;
+                let y = CHART_HEIGHT * (1.0 - (elo(p)? - min) / range);
+                Some(format!("{x:.1},{y:.1}"))
+            })
+            .collect();
+        format!(
+            r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="2" />"#,
+            points.join(" ")
+        )
+    };
+    format!(
+        r#"<svg width="{CHART_WIDTH}" height="{CHART_HEIGHT}" style="overflow: visible"><title>{min:.0} – {max:.0}</title>{}{}</svg>"#,
+        line(|p| p.rollman_elo, "#1f77b4"),
+        line(|p| p.ghost_elo, "#d62728"),
+    )
+}
+
+impl Stats {
+    pub fn users(&self) -> Vec<User<'_>> {
+        let mut by_user = BTreeMap::<&str, Vec<_>>::new();
+        for (token, agent) in &self.agents {
+            by_user
+                .entry(agent.user.as_str())
+                .or_default()
+                .push((token.as_str(), agent));
+        }
+        let mut past = BTreeMap::<&str, Vec<_>>::new();
+        for (&logic_version, season) in &self.seasons {
+            let mut agents: Vec<_> = season.agents.values().collect();
+            agents.sort_by_key(|a| (a.version, &a.name));
+            for a in agents {
+                past.entry(a.user.as_str()).or_default().push(RatingPoint {
+                    logic_version,
+                    name: &a.name,
+                    version: a.version,
+                    rollman_elo: (a.rollman_count > 0).then_some(a.rollman_elo),
+                    ghost_elo: (a.ghost_count > 0).then_some(a.ghost_elo),
+                });
+            }
+        }
+        // Users who only played in past seasons.
+        for &name in past.keys() {
+            by_user.entry(name).or_default();
+        }
+        by_user
+            .into_iter()
+            .map(|(name, agents)| {
+                let past = past.remove(name).unwrap_or_default();
+                User::new(name, agents, past, self.logic_version)
+            })
+            .collect()
+    }
+
+    pub fn save_users(&self, out: &Path) -> Result<()> {
+        let mut buf = AtomicFile::create(out.join("users.html"))?;
+
+        let mut users = self.users();
+        users.sort_by_key(|u| {
+            Reverse(OrderedFloat(
+                u.best_rollman.map_or(0.0, |(_, a)| a.rollman_elo)
+                    + u.best_ghost.map_or(0.0, |(_, a)| a.ghost_elo),
+            ))
+        });
+
+        let mut draw_counts = HashMap::<&str, (usize, usize)>::new();
+        for draw in self.draws.values() {
+            for token in [&draw.rollman, &draw.ghost] {
+                let counts = draw_counts.entry(token.as_str()).or_default();
+                match draw.kind {
+                    DrawKind::Tie => counts.0 += 1,
+                    DrawKind::DoubleFailure { .. } => counts.1 += 1,
+                }
+            }
+        }
+
+        write!(
+            buf,
+            r#"<!DOCTYPE html>
+<html>
+
+<head>
+  <title>RollMan Users</title>
+  <style>
+    table {{ border-collapse: collapse; margin-top: 1rem; }}
+    th, td {{ padding: 10px; border: 1px solid #ddd; }}
+    th {{ background-color: #f5f5f5; }}
+    section {{ margin: 2rem; }}
+  </style>
+  <script defer data-domain="misc.ouuan.moe" src="https://plausible.ouuan.moe/js/script.js"></script>
+</head>
+
+<body>
+  <div>
+    <a href="ranking.html">Ranking</a>
+    最后更新于 {}
+  </div>
+  <section>
+    <h2>Users</h2>
+    <table>
+      <tr>
+        <th>User</th>
+        <th>Best Rollman</th>
+        <th>Best Ghost</th>
+        <th title="bot 数">#B</th>
+        <th title="对局数">#M</th>
+        <th title="fail 率">F%</th>
+      </tr>"#,
+            Local::now().format("%F %T"),
+        )?;
+
+        for user in &users {
+            write!(
+                buf,
+                r##"
+      <tr>
+        <td><a href="#{}">{}</a></td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td><td>{}</td><td>{:.1}</td>
+      </tr>"##,
+                escape_html(user.name),
+                escape_html(user.name),
+                user.best_rollman.map_or(String::new(), |(_, a)| format!(
+                    "{} v{} <span {}>{:.0}</span>",
+                    escape_html(&a.name),
+                    a.version,
+                    rating_color(a.rollman_elo),
+                    a.rollman_elo
+                )),
+                user.best_ghost.map_or(String::new(), |(_, a)| format!(
+                    "{} v{} <span {}>{:.0}</span>",
+                    escape_html(&a.name),
+                    a.version,
+                    rating_color(a.ghost_elo),
+                    a.ghost_elo
+                )),
+                user.timeline.len(),
+                user.match_count(),
+                user.failure_rate() * 100.0,
+            )?;
+        }
+        write!(
+            buf,
+            "
+    </table>
+  </section>"
+        )?;
+
+        for user in &users {
+            write!(
+                buf,
+                r#"
+  <section id="{}">
+    <h3><a href="https://www.saiblo.net/user/{}">{}</a></h3>
+    <table>
+      <tr>
+        <th>Bot</th>
+        <th>Ver.</th>
+        <th title="首局 id">First</th>
+        <th>Rollman Elo</th>
+        <th title="rollman 对局数">#R</th>
+        <th>Ghost Elo</th>
+        <th title="ghost 对局数">#G</th>
+        <th title="平局数">#T</th>
+        <th title="双方均失败的对局数">#DF</th>
+        <th title="fail 数">#F</th>
+        <th>Failures</th>
+      </tr>"#,
+                escape_html(user.name),
+                escape_html(user.name),
+                escape_html(user.name),
+            )?;
+            for (token, agent) in &user.timeline {
+                let first = agent.first_time();
+                let (ties, double_failures) = draw_counts.get(token).copied().unwrap_or_default();
+                write!(
+                    buf,
+                    r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td>
+        <td {}>{:.0}</td><td>{}</td>
+        <td {}>{:.0}</td><td>{}</td>
+        <td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+      </tr>"#,
+                    escape_html(&agent.name),
+                    agent.version,
+                    if first == u32::MAX {
+                        String::new()
+                    } else {
+                        first.to_string()
+                    },
+                    rating_color(agent.rollman_elo),
+                    agent.rollman_elo,
+                    agent.rollman_count,
+                    rating_color(agent.ghost_elo),
+                    agent.ghost_elo,
+                    agent.ghost_count,
+                    ties,
+                    double_failures,
+                    agent.failure.len(),
+                    [Some(Role::Rollman), Some(Role::Ghost), None]
+                        .into_iter()
+                        .filter_map(|role| {
+                            let failures = agent.failure.values().filter(|f| f.role == role);
+                            let breakdown = failure_breakdown(failures);
+                            (!breakdown.is_empty()).then(|| match role {
+                                Some(role) => format!("{role}: {breakdown}"),
+                                None => breakdown,
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                        .join("; "),
+                )?;
+            }
+            write!(
+                buf,
+                r#"
+    </table>
+    <h4>Rating progression</h4>
+    {}
+    <table>
+      <tr>
+        <th title="逻辑版本">Season</th>
+        <th>Bot</th>
+        <th>Ver.</th>
+        <th>Rollman Elo</th>
+        <th>Ghost Elo</th>
+      </tr>"#,
+                progression_chart(&user.progression),
+            )?;
+            let elo = |elo: Option<f32>| {
+                elo.map_or("<td></td>".to_string(), |elo| {
+                    format!("<td {}>{elo:.0}</td>", rating_color(elo))
+                })
+            };
+            for point in &user.progression {
+                write!(
+                    buf,
+                    r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td>{}{}
+      </tr>"#,
+                    point.logic_version,
+                    escape_html(point.name),
+                    point.version,
+                    elo(point.rollman_elo),
+                    elo(point.ghost_elo),
+                )?;
+            }
+            write!(
+                buf,
+                r#"
+    </table>
+    <table>
+      <tr>
+        <th>Bot</th>
+        <th>Ver.</th>
+        <th>Role</th>
+        <th title="对局数">#M</th>
+        <th>Mean</th>
+        <th>Std</th>
+        <th>Min</th>
+        <th>P25</th>
+        <th>P50</th>
+        <th>P75</th>
+        <th>Max</th>
+        <th title="近{TREND_WINDOW}局的得分趋势（每局）">Trend</th>
+      </tr>"#
+            )?;
+            for (token, agent) in &user.timeline {
+                for (role, scores) in [
+                    ("Rollman", self.rollman_score_stats(token)),
+                    ("Ghost", self.ghost_score_stats(token)),
+                ] {
+                    let Some(s) = scores else {
+                        continue;
+                    };
+                    write!(
+                        buf,
+                        r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+        <td>{:.1}</td><td>{:.1}</td><td>{}</td>
+        <td>{:.1}</td><td>{:.1}</td><td>{:.1}</td>
+        <td>{}</td><td>{:+.2}</td>
+      </tr>"#,
+                        escape_html(&agent.name),
+                        agent.version,
+                        role,
+                        s.count,
+                        s.mean,
+                        s.std_dev,
+                        s.min,
+                        s.quantiles[0],
+                        s.quantiles[1],
+                        s.quantiles[2],
+                        s.max,
+                        s.trend,
+                    )?;
+                }
+            }
+            write!(
+                buf,
+                r#"
+    </table>
+    <table>
+      <tr>
+        <th>Bot</th>
+        <th>Ver.</th>
+        <th>Role</th>
+        <th title="有回放分析的对局数">#M</th>
+        <th title="平均回合数">Len</th>
+        <th title="平均领先方变化次数">Lead±</th>
+        <th title="平均最终比分达成回合">Final@</th>
+        <th title="平均每回合移动距离">Speed</th>
+        <th>Actions</th>
+      </tr>"#
+            )?;
+            for (token, agent) in &user.timeline {
+                for (role, analytics) in [
+                    ("Rollman", self.rollman_analytics(token)),
+                    ("Ghost", self.ghost_analytics(token)),
+                ] {
+                    let Some(a) = analytics else {
+                        continue;
+                    };
+                    write!(
+                        buf,
+                        r#"
+      <tr>
+        <td>{}</td><td>{}</td><td>{}</td><td>{}</td>
+        <td>{:.1}</td><td>{:.2}</td><td>{:.1}</td><td>{:.2}</td>
+        <td>{}</td>
+      </tr>"#,
+                        escape_html(&agent.name),
+                        agent.version,
+                        role,
+                        a.count,
+                        a.mean_length,
+                        a.mean_lead_changes,
+                        a.mean_final_score_at,
+                        a.speed,
+                        escape_html(&a.top_actions(3)),
+                    )?;
+                }
+            }
+            write!(
+                buf,
+                "
+    </table>
+  </section>"
+            )?;
+        }
+
+        writeln!(
+            buf,
+            r#"
+</body>
+
+</html>"#
+        )?;
+        buf.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progression_across_seasons() {
+        let mut stats = Stats {
+            logic_version: 4,
+            ..Default::default()
+        };
+        let m = |rollman: &str, ghost: &str, logic_version| Match {
+            rollman: rollman.to_string(),
+            ghost: ghost.to_string(),
+            rollman_score: 7,
+            ghost_score: 2,
+            logic_version,
+            created_at: None,
+            finished_at: None,
+            room_id: None,
+            creator: None,
+        };
+        for token in ["a1", "b1"] {
+            stats.add_agent(token, format!("user-{}", &token[..1]), "bot".to_string(), 1);
+        }
+        stats.add_agent("c1", "user-c".to_string(), "bot".to_string(), 1);
+        stats.add_match(1, m("a1", "b1", 4));
+        stats.add_match(3, m("c1", "b1", 4));
+        stats.archive(5);
+        for token in ["a2", "b2"] {
+            stats.add_agent(token, format!("user-{}", &token[..1]), "bot".to_string(), 2);
+        }
+        stats.add_match(2, m("b2", "a2", 5));
+
+        let users = stats.users();
+        let alice = users.iter().find(|u| u.name == "user-a").unwrap();
+        let points: Vec<_> = alice
+            .progression
+            .iter()
+            .map(|p| {
+                (
+                    p.logic_version,
+                    p.version,
+                    p.rollman_elo.is_some(),
+                    p.ghost_elo.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(points, [(4, 1, true, false), (5, 2, false, true)]);
+        assert_eq!(
+            alice.progression[1].ghost_elo,
+            Some(stats.agents["a2"].ghost_elo)
+        );
+
+        let carol = users.iter().find(|u| u.name == "user-c").unwrap();
+        assert!(carol.timeline.is_empty());
+        assert_eq!(carol.progression.len(), 1);
+        assert_eq!(carol.progression[0].logic_version, 4);
+
+        let chart = progression_chart(&alice.progression);
+        assert_eq!(chart.matches("<polyline").count(), 2);
+        assert!(progression_chart(&alice.progression[..1]).is_empty());
+    }
+}
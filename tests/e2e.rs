@@ -0,0 +1,116 @@
+//! Runs the binary against `mock_saiblo` through fetch, rate, schedule and report.
+
+mod mock_saiblo;
+
+use mock_saiblo::{MockSaiblo, TOKEN};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rollman-elo-e2e-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_config(dir: &Path, saiblo: &MockSaiblo) {
+    let config = format!(
+        r#"
+[saiblo]
+base_url = "{}"
+token = "{TOKEN}"
+
+[api]
+requests_per_second = 0.0
+backoff_ms = 10
+max_backoff_ms = 50
+
+[storage]
+path = "storage.sqlite"
+
+[schedule]
+min_rating = 0.0
+blackouts = []
+"#,
+        saiblo.base_url
+    );
+    fs::write(dir.join("rollman.toml"), config).unwrap();
+}
+
+/// Runs the binary in `dir` without the environment of the test, returning its stdout.
+fn run(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rollman-elo"))
+        .args(args)
+        .current_dir(dir)
+        .env_clear()
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        output.status.success(),
+        "{args:?} failed:\n{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+#[test]
+fn fetch_rate_schedule_report() {
+    let saiblo = MockSaiblo::start("tests/fixtures/saiblo");
+    let dir = work_dir("pipeline");
+    write_config(&dir, &saiblo);
+
+    let fetched = run(&dir, &["fetch", "--max-pages", "2"]);
+    assert!(fetched.contains("Collected 3 matches"), "{fetched}");
+    for report in ["elo.csv", "ranking.html"] {
+        assert!(dir.join(report).exists(), "{report} is missing");
+    }
+
+    let ranking = run(&dir, &["rate", "--top", "1"]);
+    assert!(ranking.contains("alice/pacer v2"), "{ranking}");
+
+    let agent = run(&dir, &["query", "agent", "token-carol"]);
+    assert!(agent.contains("failures: 1"), "{agent}");
+
+    run(&dir, &["schedule", "--count", "2"]);
+    {
+        let state = saiblo.state.lock().unwrap();
+        assert_eq!(state.rooms.len(), 2);
+        assert!(state.rooms.iter().all(|players| players.len() == 2));
+        assert_eq!(state.begun, [0, 1]);
+    }
+
+    // The judging match and both new ones count against `max_matches`.
+    fs::write(
+        dir.join("rollman.toml"),
+        fs::read_to_string(dir.join("rollman.toml")).unwrap() + "max_matches = 4\n",
+    )
+    .unwrap();
+    let scheduled = run(&dir, &["schedule"]);
+    assert!(scheduled.contains("Creating 1 matches"), "{scheduled}");
+
+    let out = dir.join("out");
+    run(&dir, &["report", "--out", out.to_str().unwrap()]);
+    let elo = fs::read_to_string(out.join("elo.csv")).unwrap();
+    assert!(elo.contains("bob,chaser,1,"), "{elo}");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn retries_unavailable_server() {
+    let saiblo = MockSaiblo::start("tests/fixtures/saiblo");
+    saiblo.state.lock().unwrap().failures = 2;
+    let dir = work_dir("retries");
+    write_config(&dir, &saiblo);
+
+    let fetched = run(&dir, &["fetch", "--max-pages", "1"]);
+    assert!(fetched.contains("Collected 3 matches"), "{fetched}");
+    let log = &saiblo.state.lock().unwrap().log;
+    assert!(log[..3]
+        .iter()
+        .all(|request| request.starts_with("GET /api/matches/?")));
+
+    fs::remove_dir_all(dir).unwrap();
+}
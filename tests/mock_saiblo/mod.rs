@@ -0,0 +1,239 @@
+//! A local stand-in for the Saiblo endpoints used by rollman-elo, seeded from
+//! `tests/fixtures/saiblo`: `matches.json` lists the matches newest first, and
+//! `replays/<id>.jsonl` is the replay of each of them.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+pub const TOKEN: &str = "mock-token";
+
+#[derive(Default)]
+pub struct State {
+    /// Newest first.
+    pub matches: Vec<Value>,
+    pub replays: HashMap<u32, Vec<u8>>,
+    /// The entities that joined each room, by order.
+    pub rooms: Vec<Vec<String>>,
+    /// Rooms whose match has begun.
+    pub begun: Vec<u32>,
+    /// How many of the next requests fail with 503.
+    pub failures: usize,
+    /// Every request as "METHOD /path?query".
+    pub log: Vec<String>,
+}
+
+pub struct MockSaiblo {
+    pub state: Arc<Mutex<State>>,
+    pub base_url: String,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockSaiblo {
+    pub fn start(fixtures: impl AsRef<Path>) -> Self {
+        let fixtures = fixtures.as_ref();
+        let matches: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(fixtures.join("matches.json")).unwrap())
+                .unwrap();
+        let mut replays = HashMap::new();
+        for m in &matches {
+            let id = m["id"].as_u64().unwrap() as u32;
+            if let Ok(replay) = fs::read(fixtures.join(format!("replays/{id}.jsonl"))) {
+                replays.insert(id, replay);
+            }
+        }
+        let state = Arc::new(Mutex::new(State {
+            matches,
+            replays,
+            ..Default::default()
+        }));
+
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let port = server.server_addr().to_ip().unwrap().port();
+        let thread = thread::spawn({
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            }
+        });
+
+        Self {
+            state,
+            base_url: format!("http://127.0.0.1:{port}/api"),
+            server,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for MockSaiblo {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle(state: &Mutex<State>, mut request: Request) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let authorized = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Authorization") && h.value.as_str() == TOKEN);
+
+    let mut state = state.lock().unwrap();
+    state
+        .log
+        .push(format!("{} {}", request.method(), request.url()));
+    let (status, response) = if state.failures > 0 {
+        state.failures -= 1;
+        (503, b"Service Unavailable".to_vec())
+    } else if !authorized {
+        (401, b"Unauthorized".to_vec())
+    } else {
+        route(&mut state, request.method(), request.url(), &body)
+    };
+    drop(state);
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let _ = request.respond(
+        Response::from_data(response)
+            .with_status_code(status)
+            .with_header(content_type),
+    );
+}
+
+fn route(state: &mut State, method: &Method, url: &str, body: &str) -> (u16, Vec<u8>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query: HashMap<_, _> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["api", "matches"]) => {
+            let number = |key: &str, default| {
+                query
+                    .get(key)
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default)
+            };
+            let state_filter = query.get("state").map(|s| decode(s));
+            let matches: Vec<_> = state
+                .matches
+                .iter()
+                .filter(|m| state_filter.as_deref().is_none_or(|s| m["state"] == s))
+                .collect();
+            let results: Vec<_> = matches
+                .iter()
+                .skip(number("offset", 0))
+                .take(number("limit", 20))
+                .collect();
+            ok(json!({ "count": matches.len(), "results": results }))
+        }
+        (Method::Get, ["api", "matches", id]) => {
+            let m = state
+                .matches
+                .iter()
+                .find(|m| id.parse().is_ok_and(|id: u64| m["id"] == id));
+            match m {
+                Some(m) => ok(m.clone()),
+                None => (404, b"Not Found".to_vec()),
+            }
+        }
+        (Method::Get, ["api", "matches", id, "download"]) => {
+            match id.parse().ok().and_then(|id: u32| state.replays.get(&id)) {
+                Some(replay) => (200, replay.clone()),
+                None => (404, b"Not Found".to_vec()),
+            }
+        }
+        (Method::Post, ["api", "rooms"]) => {
+            let info: Value = serde_json::from_str(body).unwrap_or_default();
+            if info["player_number"] != 2 {
+                return (400, b"Bad Request".to_vec());
+            }
+            state.rooms.push(Vec::new());
+            ok(json!({ "id": state.rooms.len() - 1 }))
+        }
+        (Method::Post, ["api", "rooms", id, "join"]) => {
+            let join: Value = serde_json::from_str(body).unwrap_or_default();
+            let Some(players) = id
+                .parse()
+                .ok()
+                .and_then(|id: usize| state.rooms.get_mut(id))
+            else {
+                return (404, b"Not Found".to_vec());
+            };
+            if join["order"] != players.len() {
+                return (400, b"Bad Request".to_vec());
+            }
+            players.push(join["entity"].as_str().unwrap_or_default().to_string());
+            ok(json!({}))
+        }
+        (Method::Post, ["api", "rooms", id, "begin_match"]) => {
+            let Some(room) = id
+                .parse()
+                .ok()
+                .filter(|&id: &u32| (id as usize) < state.rooms.len())
+            else {
+                return (404, b"Not Found".to_vec());
+            };
+            state.begun.push(room);
+            // The new match waits for a judge.
+            let id = state
+                .matches
+                .iter()
+                .filter_map(|m| m["id"].as_u64())
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let player = |token: &String| {
+                json!({
+                    "code": { "id": token, "entity": token, "version": 1 },
+                    "user": { "username": token },
+                    "score": 0,
+                    "end_state": null,
+                })
+            };
+            let info: Vec<_> = state.rooms[room as usize].iter().map(player).collect();
+            state
+                .matches
+                .insert(0, json!({ "id": id, "state": "准备中", "info": info }));
+            ok(json!({}))
+        }
+        _ => (404, b"Not Found".to_vec()),
+    }
+}
+
+fn ok(value: Value) -> (u16, Vec<u8>) {
+    (200, serde_json::to_vec(&value).unwrap())
+}
+
+/// Decodes the percent-encoding of a query value.
+fn decode(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut chars = value.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'%' => {
+                let hex = [chars.next().unwrap_or(b'0'), chars.next().unwrap_or(b'0')];
+                let hex = std::str::from_utf8(&hex).unwrap_or("00");
+                bytes.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+            }
+            b'+' => bytes.push(b' '),
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}